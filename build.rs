@@ -0,0 +1,110 @@
+//! Resolves the tls-client library at build time when the `vendored` feature is enabled.
+//!
+//! The binary for the target triple is taken from (most specific first):
+//! - `RUST_TLS_CLIENT_VENDOR_PATH_<TARGET>` / `RUST_TLS_CLIENT_VENDOR_PATH`, a local file
+//! - `RUST_TLS_CLIENT_VENDOR_URL_<TARGET>` / `RUST_TLS_CLIENT_VENDOR_URL`, downloaded from a mirror.
+//!   `{target}` in the URL is replaced with the target triple.
+//!
+//! `<TARGET>` is the upper-cased target triple with `-` replaced by `_`, e.g.
+//! `RUST_TLS_CLIENT_VENDOR_PATH_X86_64_UNKNOWN_LINUX_GNU`. The binary is copied to
+//! `$OUT_DIR/tls-client-vendored` and embedded into the crate, and `RUST_TLS_CLIENT_VENDOR_SHA256`
+//! can be set to pin its digest.
+//!
+//! Without either variable (and always on docs.rs) nothing is embedded and a `cargo:warning` is
+//! emitted, the library is then resolved at runtime as without the feature.
+
+#[cfg(feature = "vendored")]
+use std::path::PathBuf;
+#[cfg(feature = "vendored")]
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "vendored")]
+const PATH_ENV: &str = "RUST_TLS_CLIENT_VENDOR_PATH";
+#[cfg(feature = "vendored")]
+const URL_ENV: &str = "RUST_TLS_CLIENT_VENDOR_URL";
+#[cfg(feature = "vendored")]
+const SHA256_ENV: &str = "RUST_TLS_CLIENT_VENDOR_SHA256";
+#[cfg(feature = "vendored")]
+const VERSION_ENV: &str = "RUST_TLS_CLIENT_VENDOR_VERSION";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "vendored")]
+    vendor();
+}
+
+#[cfg(feature = "vendored")]
+fn vendor() {
+    let target = std::env::var("TARGET").expect("TARGET is set by cargo");
+    let out = PathBuf::from(std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo")).join("tls-client-vendored");
+    println!("cargo:rerun-if-env-changed=DOCS_RS");
+
+    let bytes = match (target_env(PATH_ENV, &target), target_env(URL_ENV, &target)) {
+        // Docs need no library, and docs.rs builds have no network
+        _ if std::env::var_os("DOCS_RS").is_some() => return write_stub(&out),
+        (Some(path), _) => {
+            println!("cargo:rerun-if-changed={}", path);
+            std::fs::read(&path).unwrap_or_else(|e| panic!("Failed to read vendored library `{}`: {}", path, e))
+        }
+        (None, Some(url)) => fetch(&url.replace("{target}", &target)),
+        (None, None) => {
+            println!(
+                "cargo:warning=The `vendored` feature needs `{}` or `{}` (optionally suffixed with `_{}`), nothing was embedded.",
+                PATH_ENV, URL_ENV, target_suffix(&target),
+            );
+            return write_stub(&out);
+        }
+    };
+
+    let sha256 = hex::encode(Sha256::digest(&bytes));
+    if let Some(expected) = target_env(SHA256_ENV, &target) {
+        if !sha256.eq_ignore_ascii_case(expected.trim()) {
+            panic!("Vendored library has sha256 {}, expected {}.", sha256, expected.trim());
+        }
+    }
+
+    std::fs::write(&out, &bytes).unwrap_or_else(|e| panic!("Failed to write `{}`: {}", out.display(), e));
+
+    // Names the vendored build in the runtime cache, defaulting to its digest
+    let version = target_env(VERSION_ENV, &target).unwrap_or_else(|| format!("vendored-{}", &sha256[..16]));
+    println!("cargo:rustc-env=RUST_TLS_CLIENT_VENDORED_VERSION={}", version);
+}
+
+/// Embed nothing, which makes the crate resolve the library at runtime.
+#[cfg(feature = "vendored")]
+fn write_stub(out: &PathBuf) {
+    std::fs::write(out, []).unwrap_or_else(|e| panic!("Failed to write `{}`: {}", out.display(), e));
+    println!("cargo:rustc-env=RUST_TLS_CLIENT_VENDORED_VERSION=");
+}
+
+/// Read `{name}_{TARGET}`, falling back to `name`.
+#[cfg(feature = "vendored")]
+fn target_env(name: &str, target: &str) -> Option<String> {
+    let specific = format!("{}_{}", name, target_suffix(target));
+    println!("cargo:rerun-if-env-changed={}", specific);
+    println!("cargo:rerun-if-env-changed={}", name);
+
+    std::env::var(specific)
+        .or_else(|_| std::env::var(name))
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(feature = "vendored")]
+fn target_suffix(target: &str) -> String {
+    target.to_uppercase().replace('-', "_")
+}
+
+#[cfg(feature = "vendored")]
+fn fetch(url: &str) -> Vec<u8> {
+    use std::io::Read;
+
+    let mut bytes = Vec::new();
+    ureq::get(url)
+        .call()
+        .unwrap_or_else(|e| panic!("Failed to download vendored library from `{}`: {}", url, e))
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .unwrap_or_else(|e| panic!("Failed to download vendored library from `{}`: {}", url, e));
+    bytes
+}
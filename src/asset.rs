@@ -0,0 +1,368 @@
+use std::fmt::{Display, Formatter};
+use crate::loader::Libc;
+
+
+/// Operating system a tls-client release asset was built for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AssetOs {
+    Windows,
+    Darwin,
+    Linux,
+}
+
+/// CPU architecture a tls-client release asset was built for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AssetArch {
+    X86,
+    Amd64,
+    Arm64,
+    /// 32 bit ARM with its architecture version, e.g. `armv7`/`arm-7`
+    Arm(u8),
+    Other(String),
+}
+
+/// How the asset was built upstream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum BuildFlavour {
+    /// Native builds, e.g. `tls-client-linux-ubuntu-amd64-1.7.2.so`
+    Standard,
+    /// Cross compiled builds, e.g. `tls-client-xgo-1.7.2-linux-amd64.so`
+    Xgo,
+}
+
+
+/// A release asset filename broken down into its parts.
+///
+/// Handles both naming schemes used upstream:
+/// `tls-client-{os}[-{libc}]-{arch}-{version}.{ext}` and
+/// `tls-client-xgo-{version}-{os}[-{os version}]-{arch}[-{arm version}].{ext}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetDescriptor {
+    pub os:         AssetOs,
+    pub arch:       AssetArch,
+    /// Only set for Linux builds which name their libc, `Libc::Unknown` for labels other than
+    /// `ubuntu`/`alpine`
+    pub libc:       Option<Libc>,
+    pub version:    Option<String>,
+    pub flavour:    BuildFlavour,
+    pub extension:  String,
+}
+
+impl AssetDescriptor {
+    /// Parse a release asset filename, `None` if it is not a tls-client build.
+    pub fn parse(name: &str) -> Option<AssetDescriptor> {
+        let (stem, extension) = name.rsplit_once('.')?;
+        if !matches!(extension, "so" | "dll" | "dylib" | "h") {
+            return None;
+        }
+
+        let mut tokens = stem.strip_prefix("tls-client-")?.split('-').peekable();
+
+        let mut flavour = BuildFlavour::Standard;
+        let mut os = None;
+        let mut arch = None;
+        let mut libc = None;
+        let mut version = None;
+        // Unrecognised token after the os, the arch unless a known arch follows it
+        let mut unknown = None;
+
+        while let Some(token) = tokens.next() {
+            match token {
+                "xgo" if os.is_none() => flavour = BuildFlavour::Xgo,
+                "windows" => os = Some(AssetOs::Windows),
+                "darwin" => os = Some(AssetOs::Darwin),
+                "linux" => os = Some(AssetOs::Linux),
+                "ubuntu" => libc = Some(Libc::Glibc),
+                "alpine" => libc = Some(Libc::Musl),
+                "amd64" => arch = Some(AssetArch::Amd64),
+                "386" => arch = Some(AssetArch::X86),
+                "arm64" => arch = Some(AssetArch::Arm64),
+                "32" if os == Some(AssetOs::Windows) && arch.is_none() => arch = Some(AssetArch::X86),
+                "64" if os == Some(AssetOs::Windows) && arch.is_none() => arch = Some(AssetArch::Amd64),
+                "arm" => {
+                    // xgo names the variant in a separate token, e.g. `arm-7`
+                    let variant = tokens.next_if(|next| next.parse::<u8>().is_ok());
+                    arch = Some(AssetArch::Arm(variant.and_then(|v| v.parse().ok()).unwrap_or(7)));
+                }
+                _ if token.starts_with("armv") && token[4..].parse::<u8>().is_ok() => {
+                    arch = Some(AssetArch::Arm(token[4..].parse().ok()?));
+                }
+                _ if is_version(token) => {
+                    // Dotted numbers between the os and arch are OS versions, e.g. `windows-4.0`
+                    if os.is_none() || arch.is_some() {
+                        version = Some(token.trim_start_matches('v').to_string());
+                    }
+                }
+                _ if os.is_some() && arch.is_none() && unknown.is_none() => unknown = Some(token),
+                _ => return None,
+            }
+        }
+
+        match (&arch, unknown) {
+            (None, Some(token)) => arch = Some(AssetArch::Other(token.to_string())),
+            // e.g. `tls-client-linux-gnu-amd64-1.7.2.so`
+            (Some(_), Some(_)) if os == Some(AssetOs::Linux) => libc = Some(Libc::Unknown),
+            _ => {}
+        }
+
+        Some(AssetDescriptor {
+            os: os?,
+            arch: arch?,
+            libc,
+            version,
+            flavour,
+            extension: extension.to_string(),
+        })
+    }
+
+    /// Whether this is a loadable library rather than e.g. a C header.
+    pub fn is_library(&self) -> bool {
+        matches!(self.extension.as_str(), "so" | "dll" | "dylib")
+    }
+
+    /// Preference of this asset for `target`, lower is better. `None` if it cannot be used at all.
+    fn rank(&self, target: &TargetPlatform) -> Option<u8> {
+        if !self.is_library() || self.os != target.os || self.arch != target.arch {
+            return None;
+        }
+
+        let libc_rank = match (target.libc, self.libc) {
+            (None, _) => 0,
+            // Might be the wrong libc, only used when nothing labelled or unlabelled fits
+            (Some(_), Some(Libc::Unknown)) => 4,
+            (Some(wanted), Some(libc)) if wanted == libc => 0,
+            (Some(_), Some(_)) => return None,
+            // Unlabelled Linux builds are glibc builds
+            (Some(Libc::Glibc), None) => 1,
+            (Some(_), None) => return None,
+        };
+
+        let flavour_rank = match self.flavour {
+            BuildFlavour::Standard => 0,
+            BuildFlavour::Xgo => 2,
+        };
+
+        Some(libc_rank + flavour_rank)
+    }
+}
+
+impl Display for AssetOs {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetOs::Windows => write!(f, "windows"),
+            AssetOs::Darwin => write!(f, "darwin"),
+            AssetOs::Linux => write!(f, "linux"),
+        }
+    }
+}
+
+impl Display for AssetArch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetArch::X86 => write!(f, "386"),
+            AssetArch::Amd64 => write!(f, "amd64"),
+            AssetArch::Arm64 => write!(f, "arm64"),
+            AssetArch::Arm(variant) => write!(f, "armv{}", variant),
+            AssetArch::Other(arch) => write!(f, "{}", arch),
+        }
+    }
+}
+
+
+/// The platform an asset is being selected for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetPlatform {
+    pub os:     AssetOs,
+    pub arch:   AssetArch,
+    pub libc:   Option<Libc>,
+}
+
+impl TargetPlatform {
+    /// The platform this process is running on, `None` if upstream does not build for it.
+    pub fn current(libc: Option<Libc>) -> Option<TargetPlatform> {
+        let os = match std::env::consts::OS {
+            "windows" => AssetOs::Windows,
+            "macos" => AssetOs::Darwin,
+            "linux" => AssetOs::Linux,
+            _ => return None,
+        };
+
+        let arch = match std::env::consts::ARCH {
+            "x86" => AssetArch::X86,
+            "x86_64" => AssetArch::Amd64,
+            "aarch64" => AssetArch::Arm64,
+            "arm" => AssetArch::Arm(7),
+            _ => return None,
+        };
+
+        let libc = match os {
+            AssetOs::Linux => libc,
+            _ => None,
+        };
+
+        Some(TargetPlatform { os, arch, libc })
+    }
+
+    /// Pick the best asset for this platform from `names`.
+    ///
+    /// Native builds are preferred over xgo builds, and builds labelled with the wanted libc over
+    /// unlabelled ones. A musl target never selects a glibc build. Builds with an unrecognised
+    /// libc label are only picked when nothing else fits. Ties are broken by name so the
+    /// choice does not depend on the order upstream lists assets in.
+    pub fn select<'a, I: IntoIterator<Item = &'a str>>(&self, names: I) -> Option<&'a str> {
+        names.into_iter()
+            .filter_map(|name| {
+                let rank = AssetDescriptor::parse(name)?.rank(self)?;
+                Some((rank, name))
+            })
+            .min()
+            .map(|(_, name)| name)
+    }
+}
+
+impl Display for TargetPlatform {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.os, self.arch)?;
+        if let Some(libc) = self.libc {
+            write!(f, " ({})", libc)?;
+        }
+        Ok(())
+    }
+}
+
+
+fn is_version(token: &str) -> bool {
+    let token = token.strip_prefix('v').unwrap_or(token);
+    token.contains('.') && token.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Assets published with upstream release v1.7.2
+    const RELEASE_ASSETS: &[&str] = &[
+        "tls-client-darwin-amd64-1.7.2.dylib",
+        "tls-client-darwin-amd64-1.7.2.h",
+        "tls-client-darwin-arm64-1.7.2.dylib",
+        "tls-client-darwin-arm64-1.7.2.h",
+        "tls-client-linux-alpine-amd64-1.7.2.h",
+        "tls-client-linux-alpine-amd64-1.7.2.so",
+        "tls-client-linux-arm64-1.7.2.h",
+        "tls-client-linux-arm64-1.7.2.so",
+        "tls-client-linux-armv7-1.7.2.h",
+        "tls-client-linux-armv7-1.7.2.so",
+        "tls-client-linux-ubuntu-amd64-1.7.2.h",
+        "tls-client-linux-ubuntu-amd64-1.7.2.so",
+        "tls-client-windows-32-1.7.2.dll",
+        "tls-client-windows-32-1.7.2.h",
+        "tls-client-windows-64-1.7.2.dll",
+        "tls-client-windows-64-1.7.2.h",
+        "tls-client-xgo-1.7.2-darwin-10.12-amd64.dylib",
+        "tls-client-xgo-1.7.2-darwin-10.12-arm64.dylib",
+        "tls-client-xgo-1.7.2-linux-386.so",
+        "tls-client-xgo-1.7.2-linux-amd64.so",
+        "tls-client-xgo-1.7.2-linux-arm-5.so",
+        "tls-client-xgo-1.7.2-linux-arm-6.so",
+        "tls-client-xgo-1.7.2-linux-arm-7.so",
+        "tls-client-xgo-1.7.2-linux-arm64.so",
+        "tls-client-xgo-1.7.2-linux-ppc64le.so",
+        "tls-client-xgo-1.7.2-linux-riscv64.so",
+        "tls-client-xgo-1.7.2-linux-s390x.so",
+        "tls-client-xgo-1.7.2-windows-4.0-386.dll",
+        "tls-client-xgo-1.7.2-windows-4.0-amd64.dll",
+    ];
+
+    fn target(os: AssetOs, arch: AssetArch, libc: Option<Libc>) -> TargetPlatform {
+        TargetPlatform { os, arch, libc }
+    }
+
+    #[test]
+    fn test_parse_standard() {
+        let asset = AssetDescriptor::parse("tls-client-linux-ubuntu-amd64-1.7.2.so").unwrap();
+        assert_eq!(asset, AssetDescriptor {
+            os: AssetOs::Linux,
+            arch: AssetArch::Amd64,
+            libc: Some(Libc::Glibc),
+            version: Some("1.7.2".to_string()),
+            flavour: BuildFlavour::Standard,
+            extension: "so".to_string(),
+        });
+
+        let asset = AssetDescriptor::parse("tls-client-windows-32-v1.3.12.dll").unwrap();
+        assert_eq!(asset.arch, AssetArch::X86);
+        assert_eq!(asset.version.as_deref(), Some("1.3.12"));
+
+        let asset = AssetDescriptor::parse("tls-client-linux-armv7-1.7.2.h").unwrap();
+        assert_eq!(asset.arch, AssetArch::Arm(7));
+        assert!(!asset.is_library());
+    }
+
+    #[test]
+    fn test_parse_xgo() {
+        let asset = AssetDescriptor::parse("tls-client-xgo-1.7.2-windows-4.0-amd64.dll").unwrap();
+        assert_eq!(asset.os, AssetOs::Windows);
+        assert_eq!(asset.arch, AssetArch::Amd64);
+        assert_eq!(asset.version.as_deref(), Some("1.7.2"));
+        assert_eq!(asset.flavour, BuildFlavour::Xgo);
+
+        let asset = AssetDescriptor::parse("tls-client-xgo-1.7.2-linux-arm-5.so").unwrap();
+        assert_eq!(asset.arch, AssetArch::Arm(5));
+
+        let asset = AssetDescriptor::parse("tls-client-xgo-1.7.2-linux-s390x.so").unwrap();
+        assert_eq!(asset.arch, AssetArch::Other("s390x".to_string()));
+    }
+
+    #[test]
+    fn test_parse_all_release_assets() {
+        for name in RELEASE_ASSETS {
+            let asset = AssetDescriptor::parse(name).unwrap_or_else(|| panic!("failed to parse {}", name));
+            assert_eq!(asset.version.as_deref(), Some("1.7.2"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_other_files() {
+        assert!(AssetDescriptor::parse("checksums.txt").is_none());
+        assert!(AssetDescriptor::parse("tls-client-linux-amd64-1.7.2.tar.gz").is_none());
+        assert!(AssetDescriptor::parse("other-linux-amd64-1.7.2.so").is_none());
+    }
+
+    #[test]
+    fn test_unknown_libc_is_kept_and_ranked_last() {
+        let asset = AssetDescriptor::parse("tls-client-linux-gnu-amd64-1.7.2.so").unwrap();
+        assert_eq!(asset.arch, AssetArch::Amd64);
+        assert_eq!(asset.libc, Some(Libc::Unknown));
+
+        let names = ["tls-client-linux-gnu-amd64-1.7.2.so", "tls-client-xgo-1.7.2-linux-amd64.so"];
+        let musl = target(AssetOs::Linux, AssetArch::Amd64, Some(Libc::Musl));
+        assert_eq!(musl.select(names), Some("tls-client-linux-gnu-amd64-1.7.2.so"));
+        let glibc = target(AssetOs::Linux, AssetArch::Amd64, Some(Libc::Glibc));
+        assert_eq!(glibc.select(names), Some("tls-client-xgo-1.7.2-linux-amd64.so"));
+
+        let with_alpine = ["tls-client-linux-gnu-amd64-1.7.2.so", "tls-client-linux-alpine-amd64-1.7.2.so"];
+        assert_eq!(musl.select(with_alpine), Some("tls-client-linux-alpine-amd64-1.7.2.so"));
+    }
+
+    #[test]
+    fn test_select() {
+        let cases = [
+            (target(AssetOs::Linux, AssetArch::Amd64, Some(Libc::Glibc)), Some("tls-client-linux-ubuntu-amd64-1.7.2.so")),
+            (target(AssetOs::Linux, AssetArch::Amd64, Some(Libc::Musl)), Some("tls-client-linux-alpine-amd64-1.7.2.so")),
+            (target(AssetOs::Linux, AssetArch::Arm64, Some(Libc::Glibc)), Some("tls-client-linux-arm64-1.7.2.so")),
+            (target(AssetOs::Linux, AssetArch::Arm64, Some(Libc::Musl)), None),
+            (target(AssetOs::Linux, AssetArch::Arm(7), Some(Libc::Glibc)), Some("tls-client-linux-armv7-1.7.2.so")),
+            (target(AssetOs::Linux, AssetArch::X86, Some(Libc::Glibc)), Some("tls-client-xgo-1.7.2-linux-386.so")),
+            (target(AssetOs::Darwin, AssetArch::Arm64, None), Some("tls-client-darwin-arm64-1.7.2.dylib")),
+            (target(AssetOs::Windows, AssetArch::Amd64, None), Some("tls-client-windows-64-1.7.2.dll")),
+            (target(AssetOs::Windows, AssetArch::X86, None), Some("tls-client-windows-32-1.7.2.dll")),
+        ];
+
+        for (target, expected) in cases {
+            assert_eq!(target.select(RELEASE_ASSETS.iter().copied()), expected, "{}", target);
+            // Selection must not depend on listing order
+            assert_eq!(target.select(RELEASE_ASSETS.iter().rev().copied()), expected, "{}", target);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use lazy_static::lazy_static;
+use crate::client::TlsClient;
+use crate::cookie::Cookie;
+use crate::error::TlsClientError;
+use crate::pool::WorkerPool;
+use crate::request::RequestPayload;
+use crate::response::RequestResponse;
+
+
+/// Number of library calls the async methods run at once, further calls wait for a free thread.
+const ASYNC_PARALLELISM: usize = 32;
+
+lazy_static! {
+    // Threads of the async methods, so slow requests never occupy the blocking pool of the runtime
+    static ref ASYNC_POOL: WorkerPool = WorkerPool::new(ASYNC_PARALLELISM);
+}
+
+
+/// Run a blocking library call on the async worker pool.
+///
+/// The call always runs to completion, even if the returned future is dropped: cancelling only
+/// discards the result, and the library's copy of the response is freed by the call itself (see
+/// `LibraryString`), so a dropped future leaks nothing.
+async fn blocking<T, F>(call: F) -> Result<T, TlsClientError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, TlsClientError> + Send + 'static,
+{
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    ASYNC_POOL.spawn(move || {
+        let _ = sender.send(call());
+    });
+    receiver.await
+        .map_err(|_| TlsClientError::GeneralError(String::from("Library call panicked")))?
+}
+
+impl RequestPayload {
+    /// Async version of `send`, usable from any async runtime.
+    pub async fn send_async(&self) -> Result<RequestResponse, TlsClientError> {
+        let payload = self.clone();
+        blocking(move || payload.send()).await
+    }
+}
+
+impl TlsClient {
+    /// Async version of `get_cookies_from_session`.
+    pub async fn get_cookies_from_session_async(&self, url: &str) -> Result<String, TlsClientError> {
+        let (session, url) = (self.session(), url.to_string());
+        blocking(move || session.get_cookies(&url)).await
+    }
+
+    /// Async version of `add_cookies_to_session`.
+    pub async fn add_cookies_to_session_async(&self, url: &str, cookies: serde_json::Value) -> Result<String, TlsClientError> {
+        let (session, url) = (self.session(), url.to_string());
+        blocking(move || session.add_cookies(&url, &cookies)).await
+    }
+
+    /// Async version of `cookies`.
+    pub async fn cookies_async(&self, url: &str) -> Result<Vec<Cookie>, TlsClientError> {
+        let (session, url) = (self.session(), url.to_string());
+        blocking(move || session.cookies(&url)).await
+    }
+
+    /// Async version of `add_cookies`.
+    pub async fn add_cookies_async(&self, url: &str, cookies: &[Cookie]) -> Result<(), TlsClientError> {
+        let (session, url, cookies) = (self.session(), url.to_string(), cookies.to_vec());
+        blocking(move || session.add_typed_cookies(&url, &cookies)).await
+    }
+
+    /// Async version of `destroy_session`.
+    pub async fn destroy_session_async(&self) -> Result<String, TlsClientError> {
+        let session = self.session();
+        blocking(move || session.destroy()).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use uuid::Uuid;
+    use crate::backend::tests::{fake_client, FakeBackend};
+    use crate::cffi::tests::{fake_library, wait_until_freed};
+    use crate::client::TlsClient;
+
+    #[tokio::test]
+    async fn test_send_async() {
+        let (_, client) = fake_client(FakeBackend::default());
+
+        let res = client.get("https://example.com").send_async().await.unwrap();
+        assert_eq!(res.target, "https://example.com/");
+        assert!(client.destroy_session_async().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_future_frees_response() {
+        let methods = fake_library();
+        let client = TlsClient::default().set_backend(methods.clone());
+
+        let url = format!("https://example.com/slow/{}", Uuid::new_v4());
+        let res = tokio::time::timeout(Duration::from_millis(20), client.get(&url).send_async()).await;
+        assert!(res.is_err());
+
+        // The call carries on after its future was dropped and frees its response
+        assert!(wait_until_freed(&url));
+        assert_eq!(methods.outstanding_allocations(), 0);
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt::Debug;
+use serde::Deserialize;
+use crate::capability::LibraryInfo;
+use crate::cffi::TlsClientSharedMethods;
+use crate::error::TlsClientError;
+use crate::request::RequestPayload;
+use crate::response::RequestResponse;
+
+
+/// Transport used by `TlsClient` and `RequestPayload` to talk to a tls-client implementation.
+///
+/// `TlsClientSharedMethods` is the stock implementation backed by the shared library, but any
+/// type implementing this trait (e.g. an in-memory fake for tests) can be passed to
+/// `TlsClient::set_backend`.
+///
+/// Backends are shared between threads and must allow concurrent calls, including concurrent
+/// requests on the same session.
+pub trait TlsBackend: Debug + Send + Sync {
+    fn request(&self, payload: &RequestPayload) -> Result<RequestResponse, TlsClientError>;
+
+    fn get_cookies_from_session(&self, payload: String) -> Result<String, TlsClientError>;
+
+    fn add_cookies_to_session(&self, payload: String) -> Result<String, TlsClientError>;
+
+    fn destroy_session(&self, payload: String) -> Result<String, TlsClientError>;
+
+    /// Destroy a session on behalf of a dropped `TlsClient`, in the background. Backends which are
+    /// expensive to start must not start for this, a session cannot outlive them anyway.
+    fn release_session(&self, payload: String) -> Result<String, TlsClientError> {
+        self.destroy_session(payload)
+    }
+
+    fn destroy_all(&self) -> Result<String, TlsClientError>;
+
+    fn free_memory(&self, id: String) -> Result<(), TlsClientError>;
+
+    /// Version and capabilities of the underlying library, `None` if unknown.
+    fn library_info(&self) -> Option<LibraryInfo> {
+        None
+    }
+
+    /// Sessions held by this backend, reported when it is swapped out so they can be re-created.
+    fn active_sessions(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Strings returned by the library which have not been freed, `0` if the backend does not
+    /// track them.
+    fn outstanding_allocations(&self) -> usize {
+        0
+    }
+}
+
+impl TlsBackend for TlsClientSharedMethods {
+    fn request(&self, payload: &RequestPayload) -> Result<RequestResponse, TlsClientError> {
+        TlsClientSharedMethods::request(self, payload)
+    }
+
+    fn get_cookies_from_session(&self, payload: String) -> Result<String, TlsClientError> {
+        TlsClientSharedMethods::get_cookies_from_session(self, payload)
+    }
+
+    fn add_cookies_to_session(&self, payload: String) -> Result<String, TlsClientError> {
+        TlsClientSharedMethods::add_cookies_to_session(self, payload)
+    }
+
+    fn destroy_session(&self, payload: String) -> Result<String, TlsClientError> {
+        TlsClientSharedMethods::destroy_session(self, payload)
+    }
+
+    fn destroy_all(&self) -> Result<String, TlsClientError> {
+        TlsClientSharedMethods::destroy_all(self)
+    }
+
+    fn free_memory(&self, id: String) -> Result<(), TlsClientError> {
+        TlsClientSharedMethods::free_memory(self, id)
+    }
+
+    fn library_info(&self) -> Option<LibraryInfo> {
+        Some(self.info().clone())
+    }
+
+    fn active_sessions(&self) -> Vec<String> {
+        TlsClientSharedMethods::active_sessions(self)
+    }
+
+    fn outstanding_allocations(&self) -> usize {
+        TlsClientSharedMethods::outstanding_allocations(self)
+    }
+}
+
+/// Body of a `destroySession` call, read by backends which keep track of their live sessions.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DestroySessionPayload {
+    session_id: String,
+}
+
+impl DestroySessionPayload {
+    /// Id of the session destroyed by `payload`, `None` if it is malformed.
+    pub(crate) fn session_id(payload: &str) -> Option<String> {
+        serde_json::from_str::<Self>(payload).ok().map(|destroyed| destroyed.session_id)
+    }
+}
+
+/// Serialize the payload of a `request` call, wrapping a failure in the error of the backend.
+pub(crate) fn serialize_request(
+    payload: &RequestPayload,
+    error: impl FnOnce(String) -> TlsClientError,
+) -> Result<String, TlsClientError> {
+    serde_json::to_string(payload).map_err(|e| error(format!("failed to serialize payload: {}", e)))
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::BTreeSet;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use serde_json::Value;
+    use crate::capability::Capability;
+    use crate::client::TlsClient;
+    use crate::cookie::Cookie;
+    use super::*;
+
+    /// In-memory backend recording the payloads it is sent, shared by the crate's tests.
+    #[derive(Debug, Default)]
+    pub(crate) struct FakeBackend {
+        pub payloads: Mutex<Vec<Value>>,
+        pub sessions: Mutex<BTreeSet<String>>,
+        /// Cookies added to any session, returned for every url
+        pub cookies: Mutex<Vec<Value>>,
+        /// Ids of the destroyed sessions
+        pub destroyed: Mutex<Vec<String>>,
+        pub info: Option<LibraryInfo>,
+        /// Simulated network round trip of each request
+        pub delay: Option<Duration>,
+    }
+
+    /// A default client sending through `backend`, which is returned to inspect the calls.
+    pub(crate) fn fake_client(backend: FakeBackend) -> (Arc<FakeBackend>, TlsClient) {
+        let backend = Arc::new(backend);
+        let client = TlsClient::default().set_backend(backend.clone());
+        (backend, client)
+    }
+
+    impl TlsBackend for FakeBackend {
+        fn request(&self, payload: &RequestPayload) -> Result<RequestResponse, TlsClientError> {
+            if let Some(delay) = self.delay {
+                std::thread::sleep(delay);
+            }
+
+            let value = serde_json::to_value(payload).unwrap();
+            self.payloads.lock().unwrap().push(value.clone());
+            self.sessions.lock().unwrap().insert(value["sessionId"].as_str().unwrap().to_string());
+            Ok(RequestResponse {
+                id: "fake".to_string(),
+                session_id: value["sessionId"].as_str().unwrap().to_string(),
+                status: 200,
+                target: value["requestUrl"].as_str().unwrap().to_string(),
+                ..Default::default()
+            })
+        }
+
+        fn get_cookies_from_session(&self, _payload: String) -> Result<String, TlsClientError> {
+            Ok(serde_json::json!({ "id": "fake", "cookies": *self.cookies.lock().unwrap() }).to_string())
+        }
+
+        fn add_cookies_to_session(&self, payload: String) -> Result<String, TlsClientError> {
+            let payload: Value = serde_json::from_str(&payload).unwrap();
+            self.cookies.lock().unwrap().extend(payload["cookies"].as_array().unwrap().iter().cloned());
+            self.get_cookies_from_session(String::new())
+        }
+
+        fn destroy_session(&self, payload: String) -> Result<String, TlsClientError> {
+            let payload: Value = serde_json::from_str(&payload).unwrap();
+            let session_id = payload["sessionId"].as_str().unwrap().to_string();
+            self.sessions.lock().unwrap().remove(&session_id);
+            self.destroyed.lock().unwrap().push(session_id);
+            Ok(String::new())
+        }
+
+        fn destroy_all(&self) -> Result<String, TlsClientError> {
+            Ok(String::new())
+        }
+
+        fn free_memory(&self, _id: String) -> Result<(), TlsClientError> {
+            Ok(())
+        }
+
+        fn library_info(&self) -> Option<LibraryInfo> {
+            self.info.clone()
+        }
+
+        fn active_sessions(&self) -> Vec<String> {
+            self.sessions.lock().unwrap().iter().cloned().collect()
+        }
+    }
+
+    #[test]
+    fn test_requests_use_configured_backend() {
+        let (backend, client) = fake_client(FakeBackend::default());
+
+        let res = client.get("https://example.com").send().unwrap();
+        assert_eq!(res.get_status(), 200);
+        assert_eq!(res.target, "https://example.com/");
+
+        let payloads = backend.payloads.lock().unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["requestMethod"], "GET");
+        assert_eq!(payloads[0]["tlsClientIdentifier"], "chrome_120");
+    }
+
+    #[test]
+    fn test_unsupported_capability_is_rejected() {
+        // A build with the core exports but without `addCookiesToSession`
+        let capabilities = [Capability::Request, Capability::FreeMemory, Capability::GetCookiesFromSession, Capability::DestroySession];
+        let (backend, client) = fake_client(FakeBackend {
+            info: Some(LibraryInfo::new(Some("1.0.0".to_string()), capabilities.into_iter().collect())),
+            ..Default::default()
+        });
+        let cookies = [Cookie::new("token", "abc")];
+
+        match client.get("https://example.com").cookies(&cookies).send() {
+            Err(TlsClientError::Unsupported(msg)) => assert!(msg.contains("`addCookiesToSession`")),
+            _ => panic!("expected the request to be rejected"),
+        }
+        assert!(backend.payloads.lock().unwrap().is_empty());
+        assert!(client.get("https://example.com").send().is_ok());
+
+        let (backend, client) = fake_client(FakeBackend {
+            info: Some(LibraryInfo::new(None, Capability::REQUIRED.iter().chain(Capability::OPTIONAL).copied().collect())),
+            ..Default::default()
+        });
+        assert!(client.get("https://example.com").cookies(&cookies).send().is_ok());
+        assert_eq!(backend.cookies.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,371 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use fs2::FileExt;
+use uuid::Uuid;
+use crate::cffi::sha256_hex;
+use crate::error::TlsClientError;
+use crate::loader::{InitOptions, Libc};
+
+
+const CACHE_DIR: &str = "rust_tls_client";
+const LOCK_FILE: &str = ".lock";
+const CHECKSUM_EXTENSION: &str = "sha256";
+
+
+/// A verified library binary stored in the cache.
+#[derive(Debug, Clone)]
+pub(crate) struct CacheEntry {
+    pub path:       PathBuf,
+    pub version:    String,
+}
+
+
+/// Versioned store of downloaded tls-client binaries.
+///
+/// Entries live at `<root>/<version>/<os>-<arch>-<libc>/tls-client.<ext>` next to a `.sha256` file
+/// recording their digest. Writers hold an advisory lock on `<root>/.lock` and move complete files
+/// into place with a rename, so a concurrent reader only ever sees a missing or complete entry.
+///
+/// The recorded digests only guard against corruption, anyone able to write to the root can replace
+/// both files. They are trusted on their own only if the root is known to be private to this user.
+pub(crate) struct BinaryCache {
+    root:       PathBuf,
+    platform:   String,
+    trusted:    bool,
+}
+
+impl BinaryCache {
+    /// Cache under the configured cache dir (the per-user cache dir by default) for the platform
+    /// selected by `options`.
+    pub fn new(options: &InitOptions) -> Result<Self, TlsClientError> {
+        if let Some(root) = options.cache_dir() {
+            return Self::with_root(root, options.libc());
+        }
+
+        match user_cache_dir() {
+            Some(dir) => {
+                let mut cache = Self::with_root(dir.join(CACHE_DIR), options.libc())?;
+                cache.trusted = true;
+                Ok(cache)
+            },
+            None => Self::with_root(std::env::temp_dir().join(CACHE_DIR), options.libc()),
+        }
+    }
+
+    /// Cache under `root`. On unix the root is created private to the current user, and refused if
+    /// it belongs to someone else or can be written by others.
+    pub fn with_root(root: PathBuf, libc: Option<Libc>) -> Result<Self, TlsClientError> {
+        Ok(Self {
+            trusted: secure_root(&root)?,
+            root,
+            platform: platform_key(libc)?,
+        })
+    }
+
+    /// Whether only the current user can write to the cache, so its recorded digests can be trusted.
+    pub fn trusted(&self) -> bool {
+        self.trusted
+    }
+
+    fn entry_path(&self, version: &str) -> Result<PathBuf, TlsClientError> {
+        Ok(self.root
+            .join(sanitize(version))
+            .join(&self.platform)
+            .join(binary_name()?))
+    }
+
+    /// Entry for `version` if present and its contents match the recorded digest and `expected`.
+    ///
+    /// Without `expected` the recorded digest is only accepted from a trusted cache.
+    pub fn lookup(&self, version: &str, expected: Option<&str>) -> Option<CacheEntry> {
+        let path = self.entry_path(version).ok()?;
+        let recorded = std::fs::read_to_string(checksum_path(&path)).ok()?.trim().to_lowercase();
+
+        match expected {
+            Some(expected) if !recorded.eq_ignore_ascii_case(expected.trim()) => return None,
+            None if !self.trusted => return None,
+            _ => {},
+        }
+
+        let actual = sha256_hex(&std::fs::read(&path).ok()?);
+        if actual != recorded {
+            return None;
+        }
+
+        Some(CacheEntry { path, version: version.to_string() })
+    }
+
+    /// Newest valid entry for this platform, regardless of version.
+    pub fn latest(&self, expected: Option<&str>) -> Option<CacheEntry> {
+        let mut versions = self.versions();
+        versions.sort_by_key(|version| version_key(version));
+        versions.iter()
+            .rev()
+            .find_map(|version| self.lookup(version, expected))
+    }
+
+    /// Store `bytes` as the binary for `version`, returning the existing entry if another process
+    /// already stored the same contents.
+    pub fn store(&self, version: &str, bytes: &[u8]) -> Result<CacheEntry, TlsClientError> {
+        let path = self.entry_path(version)?;
+        let sha256 = sha256_hex(bytes);
+
+        let _lock = self.lock()?;
+        if let Some(entry) = self.lookup(version, Some(&sha256)) {
+            return Ok(entry);
+        }
+
+        write_atomic(&path, bytes)?;
+        write_atomic(&checksum_path(&path), sha256.as_bytes())?;
+
+        Ok(CacheEntry { path, version: version.to_string() })
+    }
+
+    /// Remove this platform's entries for every version other than `keep`, along with the
+    /// unversioned binaries written by older releases of this crate.
+    ///
+    /// Removal is best effort, e.g. Windows will refuse to delete a library loaded by another process.
+    pub fn prune(&self, keep: &str) {
+        let _lock = match self.lock() {
+            Ok(lock) => lock,
+            Err(_) => return,
+        };
+
+        let keep = sanitize(keep);
+        for version in self.versions().into_iter().filter(|version| *version != keep) {
+            let version_dir = self.root.join(&version);
+            let _ = std::fs::remove_dir_all(version_dir.join(&self.platform));
+            // Only succeeds once no other platform has an entry for this version
+            let _ = std::fs::remove_dir(&version_dir);
+        }
+
+        if let Ok(entries) = std::fs::read_dir(&self.root) {
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with("tls_client_binary") {
+                    let _ = std::fs::remove_file(entry.path());
+                }
+            }
+        }
+    }
+
+    fn versions(&self) -> Vec<String> {
+        let entries = match std::fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+
+        entries.flatten()
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| !name.starts_with('.'))
+            .collect()
+    }
+
+    fn lock(&self) -> Result<File, TlsClientError> {
+        std::fs::create_dir_all(&self.root)
+            .and_then(|_| OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(self.root.join(LOCK_FILE)))
+            .and_then(|file| file.lock_exclusive().map(|_| file))
+            .map_err(|e| TlsClientError::Cache(format!("Failed to lock `{}`: {}", self.root.display(), e)))
+    }
+}
+
+
+/// `$XDG_CACHE_HOME`, `~/.cache` or `%LOCALAPPDATA%`, whichever is set for this platform.
+fn user_cache_dir() -> Option<PathBuf> {
+    let dir = match cfg!(windows) {
+        true => std::env::var_os("LOCALAPPDATA").map(PathBuf::from),
+        false => std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache"))),
+    };
+    dir.filter(|dir| dir.is_absolute())
+}
+
+/// Create `root` with mode 0700 if it is missing and check that it is private to the current user.
+#[cfg(unix)]
+fn secure_root(root: &Path) -> Result<bool, TlsClientError> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let create = std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(root);
+    let metadata = create
+        .and_then(|_| std::fs::metadata(root))
+        .map_err(|e| TlsClientError::Cache(format!("Failed to create `{}`: {}", root.display(), e)))?;
+
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    if metadata.uid() != uid {
+        return Err(TlsClientError::Cache(format!("`{}` is owned by another user", root.display())));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(TlsClientError::Cache(format!("`{}` is writable by other users", root.display())));
+    }
+    Ok(true)
+}
+
+/// Permissions are not checked here, only the per-user default is trusted.
+#[cfg(not(unix))]
+fn secure_root(_root: &Path) -> Result<bool, TlsClientError> {
+    Ok(false)
+}
+
+/// Write `bytes` to a temporary file next to `path` and rename it into place.
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), TlsClientError> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let tmp = dir.join(format!(".{}.tmp", Uuid::new_v4()));
+
+    let res = std::fs::create_dir_all(dir)
+        .and_then(|_| File::create(&tmp))
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&tmp, path));
+
+    if let Err(e) = res {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(TlsClientError::Cache(format!("Failed to write `{}`: {}", path.display(), e)));
+    }
+    Ok(())
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(CHECKSUM_EXTENSION);
+    path.with_file_name(name)
+}
+
+fn binary_name() -> Result<&'static str, TlsClientError> {
+    match std::env::consts::OS {
+        "windows" => Ok("tls-client.dll"),
+        "macos" => Ok("tls-client.dylib"),
+        "linux" => Ok("tls-client.so"),
+        _ => Err(TlsClientError::Cache(String::from("Invalid OS detected."))),
+    }
+}
+
+fn platform_key(libc: Option<Libc>) -> Result<String, TlsClientError> {
+    let libc = libc.map(|libc| libc.to_string()).unwrap_or_else(|| String::from("none"));
+    Ok(format!("{}-{}-{}", std::env::consts::OS, std::env::consts::ARCH, libc))
+}
+
+/// Keep version directory names to a safe character set.
+fn sanitize(version: &str) -> String {
+    version.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect()
+}
+
+/// Sort key ordering `v1.10.0` after `v1.9.2`.
+fn version_key(version: &str) -> Vec<u64> {
+    version.trim_start_matches('v')
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+
+    fn test_cache() -> BinaryCache {
+        let root = std::env::temp_dir().join(format!("rust_tls_client_test_{}", Uuid::new_v4()));
+        BinaryCache::with_root(root, Libc::detect()).unwrap()
+    }
+
+    #[test]
+    fn test_store_and_lookup() {
+        let cache = test_cache();
+        let entry = cache.store("v1.7.2", b"binary").unwrap();
+
+        assert_eq!(std::fs::read(&entry.path).unwrap(), b"binary");
+        assert!(entry.path.starts_with(cache.root.join("v1.7.2")));
+        assert!(cache.lookup("v1.7.2", None).is_some());
+        assert!(cache.lookup("v1.7.2", Some(&sha256_hex(b"binary"))).is_some());
+        assert!(cache.lookup("v1.7.2", Some("00")).is_none());
+        assert!(cache.lookup("v1.7.1", None).is_none());
+
+        // A corrupted binary is not reused
+        std::fs::write(&entry.path, b"truncated").unwrap();
+        assert!(cache.lookup("v1.7.2", None).is_none());
+
+        std::fs::remove_dir_all(&cache.root).unwrap();
+    }
+
+    #[test]
+    fn test_untrusted_digest_needs_expected() {
+        let mut cache = test_cache();
+        cache.trusted = false;
+        cache.store("v1.7.2", b"planted").unwrap();
+
+        assert!(cache.lookup("v1.7.2", None).is_none());
+        assert!(cache.latest(None).is_none());
+        assert!(cache.lookup("v1.7.2", Some(&sha256_hex(b"binary"))).is_none());
+        assert!(cache.lookup("v1.7.2", Some(&sha256_hex(b"planted"))).is_some());
+
+        std::fs::remove_dir_all(&cache.root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared_root_is_refused() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let cache = test_cache();
+        assert_eq!(std::fs::metadata(&cache.root).unwrap().permissions().mode() & 0o777, 0o700);
+        assert!(cache.trusted);
+
+        std::fs::set_permissions(&cache.root, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(matches!(
+            BinaryCache::with_root(cache.root.clone(), Libc::detect()),
+            Err(TlsClientError::Cache(_)),
+        ));
+
+        std::fs::remove_dir_all(&cache.root).unwrap();
+    }
+
+    #[test]
+    fn test_latest_and_prune() {
+        let cache = test_cache();
+        cache.store("v1.9.2", b"old").unwrap();
+        cache.store("v1.10.0", b"new").unwrap();
+
+        assert_eq!(cache.latest(None).unwrap().version, "v1.10.0");
+
+        cache.prune("v1.10.0");
+        assert!(cache.lookup("v1.9.2", None).is_none());
+        assert!(cache.lookup("v1.10.0", None).is_some());
+
+        std::fs::remove_dir_all(&cache.root).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_store() {
+        let cache = Arc::new(test_cache());
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                std::thread::spawn(move || cache.store("v1.7.2", &[7u8; 1 << 16]).unwrap())
+            })
+            .collect();
+
+        for handle in handles {
+            let entry = handle.join().unwrap();
+            assert_eq!(std::fs::read(entry.path).unwrap(), vec![7u8; 1 << 16]);
+        }
+
+        std::fs::remove_dir_all(&cache.root).unwrap();
+    }
+}
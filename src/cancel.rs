@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+
+type Listener = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct TokenState {
+    cancelled:      bool,
+    next_listener:  u64,
+    listeners:      HashMap<u64, Listener>,
+}
+
+
+/// Cancels the requests it is attached to with `RequestPayload::cancel_token`, from any thread.
+///
+/// Clones share the same state, so a token can be handed to several requests and cancelled once.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<Mutex<TokenState>>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make every request using this token, now or later, fail with `TlsClientError::Cancelled`.
+    pub fn cancel(&self) {
+        let listeners = {
+            let mut state = self.state();
+            state.cancelled = true;
+            std::mem::take(&mut state.listeners)
+        };
+        // Run outside the lock, a listener may touch the token again
+        for (_, listener) in listeners {
+            listener();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state().cancelled
+    }
+
+    /// Run `listener` once the token is cancelled, or right away if it already is. It is dropped
+    /// without running when the returned guard is.
+    pub(crate) fn on_cancel(&self, listener: impl FnOnce() + Send + 'static) -> CancelListener<'_> {
+        let mut state = self.state();
+        if state.cancelled {
+            drop(state);
+            listener();
+            return CancelListener { token: self, id: None };
+        }
+
+        let id = state.next_listener;
+        state.next_listener += 1;
+        state.listeners.insert(id, Box::new(listener));
+        CancelListener { token: self, id: Some(id) }
+    }
+
+    fn state(&self) -> MutexGuard<'_, TokenState> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Debug for CancellationToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CancellationToken").field(&self.is_cancelled()).finish()
+    }
+}
+
+/// Registration of a listener with `CancellationToken::on_cancel`, removed when dropped.
+pub(crate) struct CancelListener<'a> {
+    token:  &'a CancellationToken,
+    id:     Option<u64>,
+}
+
+impl Drop for CancelListener<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.token.state().listeners.remove(&id);
+        }
+    }
+}
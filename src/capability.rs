@@ -0,0 +1,87 @@
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use crate::error::TlsClientError;
+
+
+/// A feature of the tls-client library, backed by one of its exports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Capability {
+    Request,
+    FreeMemory,
+    GetCookiesFromSession,
+    AddCookiesToSession,
+    DestroySession,
+    DestroyAll,
+}
+
+impl Capability {
+    /// Exports every usable build has, loading fails without them.
+    pub const REQUIRED: &'static [Capability] = &[Capability::Request, Capability::FreeMemory];
+
+    /// Exports which older builds may lack.
+    pub const OPTIONAL: &'static [Capability] = &[
+        Capability::GetCookiesFromSession,
+        Capability::AddCookiesToSession,
+        Capability::DestroySession,
+        Capability::DestroyAll,
+    ];
+
+    /// Name of the export providing this capability.
+    pub fn export(&self) -> &'static str {
+        match self {
+            Capability::Request => "request",
+            Capability::FreeMemory => "freeMemory",
+            Capability::GetCookiesFromSession => "getCookiesFromSession",
+            Capability::AddCookiesToSession => "addCookiesToSession",
+            Capability::DestroySession => "destroySession",
+            Capability::DestroyAll => "destroyAll",
+        }
+    }
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.export())
+    }
+}
+
+
+/// What is known about a loaded tls-client library.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LibraryInfo {
+    version:        Option<String>,
+    capabilities:   BTreeSet<Capability>,
+}
+
+impl LibraryInfo {
+    pub fn new(version: Option<String>, capabilities: BTreeSet<Capability>) -> Self {
+        Self { version, capabilities }
+    }
+
+    /// Release version, from the cache metadata or release tag it was loaded from, or parsed from
+    /// the file name of an explicitly configured library.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub fn capabilities(&self) -> &BTreeSet<Capability> {
+        &self.capabilities
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Fail with `TlsClientError::Unsupported` unless `capability` is available.
+    pub fn require(&self, capability: Capability) -> Result<(), TlsClientError> {
+        match self.supports(capability) {
+            true => Ok(()),
+            false => Err(TlsClientError::Unsupported(format!(
+                "the loaded tls-client{} does not export `{}`",
+                self.version.as_ref().map(|version| format!(" {}", version)).unwrap_or_default(),
+                capability.export(),
+            ))),
+        }
+    }
+}
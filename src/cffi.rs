@@ -1,222 +1,491 @@
 use std::ffi::{CStr, CString};
-use std::io::Write;
 use std::os::raw::c_char;
-use lazy_static::lazy_static;
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use libloading::{Library, Symbol};
-use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::Deserialize;
-use serde_json::Value;
+use sha2::{Digest, Sha256};
+use crate::asset::{AssetDescriptor, TargetPlatform};
+use crate::backend::{serialize_request, DestroySessionPayload};
+use crate::cache::{BinaryCache, CacheEntry};
+use crate::capability::{Capability, LibraryInfo};
 use crate::error::TlsClientError;
+use crate::loader::{InitOptions, Libc};
 use crate::response::RequestResponse;
 use crate::request::{RequestPayload};
 
 
-lazy_static! {
-    pub static ref SHARED_METHODS: TlsClientSharedMethods = TlsClientSharedMethods::new_default().unwrap();
-    // TODO: Add threadsafe impl
-    // Threadsafe
-    // static ref TS_SHARED_METHODS: Mutex<TlsClientSharedMethods> = Mutex::new(TlsClientSharedMethods::new_default().unwrap());
-}
+// Signatures of the functions exported by the tls-client shared library
+type StringFn = unsafe extern "C" fn(*const c_char) -> *const c_char;
+type NoArgFn = unsafe extern "C" fn() -> *const c_char;
+type FreeFn = unsafe extern "C" fn(*const c_char);
 
 
+/// A loaded tls-client library.
+///
+/// # Thread safety
+///
+/// The handle is `Send` and `Sync` and is meant to be shared between threads through an `Arc`,
+/// without a `Mutex` serialising calls:
+/// - the exports are cgo functions, which Go allows to be called from any thread and concurrently.
+///   The library guards its session map internally, and concurrent requests on one session share
+///   its cookie jar and connections as they would in Go.
+/// - every call returns a newly allocated string which only the calling thread reads, and which it
+///   frees by its own `id`, so calls never touch each other's memory.
+/// - the resolved exports stay valid for as long as the handle, since `_lib` is only unloaded when
+///   the last reference is dropped. Our own bookkeeping is behind atomics and a `Mutex`.
+///
+/// Use a `WorkerPool` to bound how many blocking calls run at once.
 #[derive(Debug)]
 pub struct TlsClientSharedMethods {
-    lib: Library,
+    // Resolved once at load time, only valid while `lib` is loaded. Optional exports are `None`
+    // when the loaded build lacks them.
+    request_fn:                 StringFn,
+    get_cookies_from_session_fn: Option<StringFn>,
+    add_cookies_to_session_fn:  Option<StringFn>,
+    destroy_session_fn:         Option<StringFn>,
+    destroy_all_fn:             Option<NoArgFn>,
+    free_memory_fn:             FreeFn,
+    info:                       LibraryInfo,
+    // Strings returned by the library which have not been freed yet
+    outstanding:                AtomicUsize,
+    // Sessions created by requests through this library and not destroyed since
+    sessions:                   Mutex<HashSet<String>>,
+    // Keeps the exports above loaded, `None` for the in-process exports used by tests
+    _lib:                       Option<Library>,
 }
 impl TlsClientSharedMethods {
+    /// Download (if required) and load the latest tls-client release for this system.
     pub fn new_default() -> Result<Self, TlsClientError> {
+        let bf = TlsClientBinaryDownloader::new(&InitOptions::default())?;
+        Self::load(bf.path(), Some(bf.version()))
+    }
+
+    /// Load the library at `dll_path`, failing if any of the required exports are missing.
+    ///
+    /// The version is taken from the file name when it follows upstream's asset naming.
+    pub fn new<P: AsRef<Path>>(dll_path: P) -> Result<Self, TlsClientError> {
+        let version = dll_path.as_ref()
+            .file_name()
+            .and_then(|name| AssetDescriptor::parse(&name.to_string_lossy()))
+            .and_then(|asset| asset.version);
+        Self::load(dll_path, version)
+    }
+
+    pub(crate) fn load<P: AsRef<Path>>(dll_path: P, version: Option<String>) -> Result<Self, TlsClientError> {
+        let dll_path = dll_path.as_ref();
+        let lib = unsafe {
+            Library::new(dll_path)
+                .map_err(|e| TlsClientError::LibraryLoad(format!("Failed to load `{}`: {}", dll_path.display(), e)))?
+        };
+
         unsafe {
-            let bf = TlsClientBinaryDownloader::new()
-                .map_err(TlsClientError::GeneralError)
-                .unwrap();
-            
-            Library::new(bf.path())
-                .map(|lib| Self { lib })
-                .map_err(|_| TlsClientError::GeneralError("Failed to build TLS Client".to_string()))
+            let request_fn = Self::symbol(&lib, Capability::Request)?;
+            let free_memory_fn = Self::symbol(&lib, Capability::FreeMemory)?;
+            let get_cookies_from_session_fn = Self::symbol(&lib, Capability::GetCookiesFromSession).ok();
+            let add_cookies_to_session_fn = Self::symbol(&lib, Capability::AddCookiesToSession).ok();
+            let destroy_session_fn = Self::symbol(&lib, Capability::DestroySession).ok();
+            let destroy_all_fn = Self::symbol::<NoArgFn>(&lib, Capability::DestroyAll).ok();
+
+            let mut capabilities: BTreeSet<Capability> = Capability::REQUIRED.iter().copied().collect();
+            let optional = [
+                (Capability::GetCookiesFromSession, get_cookies_from_session_fn.is_some()),
+                (Capability::AddCookiesToSession, add_cookies_to_session_fn.is_some()),
+                (Capability::DestroySession, destroy_session_fn.is_some()),
+                (Capability::DestroyAll, destroy_all_fn.is_some()),
+            ];
+            capabilities.extend(optional.iter().filter(|(_, found)| *found).map(|(capability, _)| *capability));
+
+            Ok(Self {
+                request_fn,
+                get_cookies_from_session_fn,
+                add_cookies_to_session_fn,
+                destroy_session_fn,
+                destroy_all_fn,
+                free_memory_fn,
+                info: LibraryInfo::new(version, capabilities),
+                outstanding: AtomicUsize::new(0),
+                sessions: Mutex::new(HashSet::new()),
+                _lib: Some(lib),
+            })
         }
     }
 
-    pub fn new(dll_path: &str) -> Result<Self, TlsClientError> {
-        unsafe {
-            Library::new(dll_path)
-                .map(|lib| Self { lib })
-                .map_err(|_| TlsClientError::GeneralError("Failed to build TLS Client".to_string()))
+    /// Handle calling `request_fn` and `free_memory_fn` instead of a library's exports, so tests
+    /// can observe what is freed.
+    #[cfg(test)]
+    pub(crate) fn with_exports(request_fn: StringFn, free_memory_fn: FreeFn) -> Self {
+        Self {
+            request_fn,
+            get_cookies_from_session_fn: None,
+            add_cookies_to_session_fn: None,
+            destroy_session_fn: None,
+            destroy_all_fn: None,
+            free_memory_fn,
+            info: LibraryInfo::new(None, Capability::REQUIRED.iter().copied().collect()),
+            outstanding: AtomicUsize::new(0),
+            sessions: Mutex::new(HashSet::new()),
+            _lib: None,
         }
     }
 
-    pub fn destroy_all(&self) -> String {
-        unsafe {
-            let da_func: Symbol<unsafe extern "C" fn() -> *const c_char> = self.lib.get(b"destroyAll").unwrap();
-            let char_res = da_func();
+    /// Resolve the export providing `capability`. Safety: `T` must match the export's signature,
+    /// and the returned pointer must not be used after `lib` is dropped.
+    unsafe fn symbol<T: Copy>(lib: &Library, capability: Capability) -> Result<T, TlsClientError> {
+        let name = capability.export();
+        let symbol: Symbol<T> = lib.get(name.as_bytes())
+            .map_err(|e| TlsClientError::Ffi { export: name.to_string(), reason: format!("symbol not found: {}", e) })?;
+        Ok(*symbol)
+    }
 
-            let str_res = CStr::from_ptr(char_res).to_string_lossy().into_owned();
-            str_res
-        }
+    /// Version and exports of the loaded library.
+    pub fn info(&self) -> &LibraryInfo {
+        &self.info
     }
 
-    pub fn free_memory(&self, id: String) {
-        unsafe {
-            let cstring = CString::new(id).unwrap();
-            let char_ptr = cstring.as_ptr();
+    fn optional<T: Copy>(&self, func: Option<T>, capability: Capability) -> Result<T, TlsClientError> {
+        self.info.require(capability)?;
+        func.ok_or_else(|| TlsClientError::Unsupported(format!("`{}` is not exported", capability.export())))
+    }
 
-            let fm_func: Symbol<unsafe extern "C" fn(*const c_char)> = self.lib.get(b"freeMemory").unwrap();
-            fm_func(char_ptr);
+    /// Copy the string returned by `export` into a guard which frees it, failing on a null pointer
+    /// instead of dereferencing it.
+    unsafe fn read_result(&self, char_res: *const c_char, export: &str) -> Result<LibraryString<'_>, TlsClientError> {
+        if char_res.is_null() {
+            return Err(TlsClientError::Ffi { export: export.to_string(), reason: String::from("returned a null pointer") });
         }
+        let value = CStr::from_ptr(char_res).to_string_lossy().into_owned();
+        Ok(LibraryString::new(self, value))
     }
 
-    fn string_to_string(&self, payload: String, identifier: &[u8]) -> String {
-        unsafe {
-            let cstring = CString::new(payload).unwrap();
-            let char_ptr = cstring.as_ptr();
+    /// Number of strings returned by the library which have not been freed.
+    ///
+    /// Responses without an `id` cannot be freed and stay counted here, so a non-zero value once
+    /// all calls have returned indicates a leak.
+    pub fn outstanding_allocations(&self) -> usize {
+        self.outstanding.load(Ordering::SeqCst)
+    }
+
+    fn to_cstring(value: String, export: &str) -> Result<CString, TlsClientError> {
+        CString::new(value)
+            .map_err(|e| TlsClientError::Ffi { export: export.to_string(), reason: format!("argument contains a NUL byte: {}", e) })
+    }
+
+    /// Ids of the sessions which live in this library, i.e. used by a request and not destroyed.
+    pub fn active_sessions(&self) -> Vec<String> {
+        let mut sessions: Vec<String> = self.sessions.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect();
+        sessions.sort();
+        sessions
+    }
+
+    pub fn destroy_all(&self) -> Result<String, TlsClientError> {
+        let destroy_all_fn = self.optional(self.destroy_all_fn, Capability::DestroyAll)?;
+        let res = unsafe {
+            let char_res = destroy_all_fn();
+            self.read_result(char_res, "destroyAll").map(LibraryString::into_string)?
+        };
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner).clear();
+        Ok(res)
+    }
 
-            let request_func: Symbol<unsafe extern "C" fn(*const c_char) -> *const c_char> = self.lib.get(identifier).unwrap();
-            let char_res = request_func(char_ptr);
+    pub fn free_memory(&self, id: String) -> Result<(), TlsClientError> {
+        let cstring = Self::to_cstring(id, "freeMemory")?;
+        unsafe {
+            (self.free_memory_fn)(cstring.as_ptr());
+        }
+        Ok(())
+    }
 
-            let str_res = CStr::from_ptr(char_res).to_string_lossy().into_owned();
-            str_res
+    fn string_to_string(&self, payload: String, func: StringFn, export: &str) -> Result<String, TlsClientError> {
+        let cstring = Self::to_cstring(payload, export)?;
+        unsafe {
+            let char_res = func(cstring.as_ptr());
+            self.read_result(char_res, export).map(LibraryString::into_string)
         }
     }
 
-    pub fn request(&self, payload: &RequestPayload) -> RequestResponse {
-        let res: RequestResponse = serde_json::from_str(
-            self.string_to_string(
-                serde_json::to_string(payload).unwrap(),
-                b"request",
-            ).trim()
-        ).unwrap();
+    pub fn request(&self, payload: &RequestPayload) -> Result<RequestResponse, TlsClientError> {
+        if let Some(session_id) = payload.session_id() {
+            self.sessions.lock().unwrap_or_else(PoisonError::into_inner).insert(session_id.to_string());
+        }
 
-        // dealloc res from mem
-        self.free_memory(res.get_id());
+        let payload = serialize_request(payload, |reason| TlsClientError::Ffi { export: String::from("request"), reason })?;
+        serde_json::from_str(
+            self.string_to_string(payload, self.request_fn, "request")?.trim()
+        ).map_err(|e| TlsClientError::Ffi { export: String::from("request"), reason: format!("invalid response: {}", e) })
+    }
 
-        res
+    pub fn get_cookies_from_session(&self, payload: String) -> Result<String, TlsClientError> {
+        let func = self.optional(self.get_cookies_from_session_fn, Capability::GetCookiesFromSession)?;
+        self.string_to_string(payload, func, "getCookiesFromSession")
     }
 
-    pub fn get_cookies_from_session(&self, payload: String) -> String {
-        self.string_to_string(payload, b"getCookiesFromSession")
+    pub fn add_cookies_to_session(&self, payload: String) -> Result<String, TlsClientError> {
+        let func = self.optional(self.add_cookies_to_session_fn, Capability::AddCookiesToSession)?;
+        self.string_to_string(payload, func, "addCookiesToSession")
+    }
+
+    pub fn destroy_session(&self, payload: String) -> Result<String, TlsClientError> {
+        let func = self.optional(self.destroy_session_fn, Capability::DestroySession)?;
+        let res = self.string_to_string(payload.clone(), func, "destroySession")?;
+        if let Some(session_id) = DestroySessionPayload::session_id(&payload) {
+            self.sessions.lock().unwrap_or_else(PoisonError::into_inner).remove(&session_id);
+        }
+        Ok(res)
     }
+}
+
 
-    pub fn add_cookies_to_session(&self, payload: String) -> String {
-        self.string_to_string(payload, b"addCookiesToSession")
+/// A string returned by the library, released through `freeMemory` when dropped.
+///
+/// The library keeps every string it returns until it is freed by the `id` field of the JSON
+/// response, so each call result must pass through this guard to avoid leaking.
+struct LibraryString<'a> {
+    methods:    &'a TlsClientSharedMethods,
+    id:         Option<String>,
+    value:      String,
+}
+impl<'a> LibraryString<'a> {
+    fn new(methods: &'a TlsClientSharedMethods, value: String) -> Self {
+        methods.outstanding.fetch_add(1, Ordering::SeqCst);
+        Self {
+            methods,
+            id: response_id(&value),
+            value,
+        }
     }
 
-    pub fn destroy_session(&self, payload: String) -> String {
-        self.string_to_string(payload, b"destroySession")
+    fn into_string(mut self) -> String {
+        std::mem::take(&mut self.value)
+    }
+}
+impl Drop for LibraryString<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            if self.methods.free_memory(id).is_ok() {
+                self.methods.outstanding.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
     }
 }
-impl Default for TlsClientSharedMethods {
-    fn default() -> Self {
-        TlsClientSharedMethods::new_default().unwrap()
+
+/// The `id` the library allocated a response string under.
+fn response_id(value: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Response {
+        id: String,
     }
+
+    serde_json::from_str::<Response>(value.trim())
+        .ok()
+        .map(|res| res.id)
+        .filter(|id| !id.is_empty())
 }
 
 
-struct TlsClientBinaryDownloader(pub String);
+pub(crate) struct TlsClientBinaryDownloader(pub CacheEntry);
 impl TlsClientBinaryDownloader {
-    pub fn new() -> Result<Self, String> {
-        let info = Self::get_latest_info()?;
-        let url = Self::parse_for_file(info)?;
-        let path = Self::download_save_file(url)?;
-        Ok(Self(path))
+    pub fn new(options: &InitOptions) -> Result<Self, TlsClientError> {
+        let client = Self::http_client(options)?;
+        let release = Self::get_release_info(&client, options)?;
+        let asset = Self::parse_for_file(&release.assets, options.libc())?;
+
+        // Prefer the caller's checksum, then one published alongside the release
+        let expected = match options.sha256() {
+            Some(sha256) => Some(sha256),
+            None => Self::published_checksum(&client, options, &release, &asset.get_name())?,
+        };
+
+        // Another process may have fetched this release since we last looked
+        let cache = BinaryCache::new(options)?;
+        if let Some(entry) = cache.lookup(&release.tag_name, expected.as_deref()) {
+            return Ok(Self(entry));
+        }
+
+        let bytes = Self::download_file(&client, Self::asset_url(options, &release, &asset))?;
+        if let Some(expected) = expected {
+            verify_sha256(&bytes, &expected)?;
+        }
+
+        let entry = cache.store(&release.tag_name, &bytes)?;
+        cache.prune(&release.tag_name);
+        Ok(Self(entry))
     }
     
-    fn download_save_file(url: String) -> Result<String, String> {
-        let cli = reqwest::blocking::Client::new();
-        let req = cli.get(url)
+    /// Client sending the configured headers (e.g. mirror credentials) with every request.
+    fn http_client(options: &InitOptions) -> Result<reqwest::blocking::Client, TlsClientError> {
+        let mut header_map = HeaderMap::new();
+        header_map.insert("user-agent", HeaderValue::from_static("rust_tls_client"));
+        for (key, value) in options.download_headers() {
+            let name = HeaderName::from_bytes(key.as_bytes())
+                .map_err(|e| TlsClientError::Download(format!("Invalid header name `{}`: {}", key, e)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| TlsClientError::Download(format!("Invalid value for header `{}`: {}", key, e)))?;
+            header_map.insert(name, value);
+        }
+
+        reqwest::blocking::Client::builder()
+            .default_headers(header_map)
+            .build()
+            .map_err(|e| TlsClientError::Download(format!("Failed to build HTTP client: {}", e)))
+    }
+
+    /// Download location of `asset`, rewritten onto the configured mirror if there is one.
+    fn asset_url(options: &InitOptions, release: &ReleaseInfo, asset: &AssetEntry) -> String {
+        match options.download_url() {
+            Some(base) => format!("{}/{}/{}", base.trim_end_matches('/'), release.tag_name, asset.name),
+            None => asset.get_browser_url(),
+        }
+    }
+
+    fn download_file(client: &reqwest::blocking::Client, url: String) -> Result<Vec<u8>, TlsClientError> {
+        let req = client.get(url)
             .send()
-            .map_err(|_| String::from("Failed to download file."))?
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| TlsClientError::Download(format!("Failed to download file: {}", e)))?
             .bytes()
-            .map_err(|_| String::from("Failed to convert to bytes."))?
+            .map_err(|e| TlsClientError::Download(format!("Failed to convert to bytes: {}", e)))?
             .to_vec();
+        Ok(req)
+    }
 
-        let filename = "tls_client_binary".to_owned() + match std::env::consts::OS {
-            "windows" => ".dll",
-            "macos" => ".dylib",
-            "linux" => ".so",
-            _ => return Err(String::from("Invalid OS detected.")),
-        };
+    /// A previously downloaded binary usable with `options`, if there is one.
+    ///
+    /// Uses the pinned version if set, otherwise the newest cached version. Unless the cache is
+    /// trusted, a binary is only picked if it matches the configured SHA-256.
+    pub fn cached(options: &InitOptions) -> Option<Self> {
+        let cache = BinaryCache::new(options).ok()?;
+        let sha256 = options.sha256();
+        if sha256.is_none() && !cache.trusted() {
+            return None;
+        }
 
-        let mut dir = std::env::temp_dir();
+        match options.version() {
+            Some(version) => cache.lookup(&version, sha256.as_deref()),
+            None => cache.latest(sha256.as_deref()),
+        }.map(Self)
+    }
 
-        dir.push("rust_tls_client");
-        let _ = std::fs::create_dir_all(&dir);
+    /// Pick the release asset for this platform, see `TargetPlatform::select`.
+    fn parse_for_file(info: &[AssetEntry], libc: Option<Libc>) -> Result<AssetEntry, TlsClientError> {
+        let target = TargetPlatform::current(libc).ok_or_else(|| TlsClientError::NoMatchingAsset(format!(
+            "tls-client is not built for {}-{}", std::env::consts::OS, std::env::consts::ARCH,
+        )))?;
 
-        dir.push(filename);
-        let fp = &dir.display().to_string();
+        let name = target.select(info.iter().map(|item| item.name.as_str()))
+            .ok_or_else(|| TlsClientError::NoMatchingAsset(format!("release has no asset for {}", target)))?;
 
-        let mut file = std::fs::File::create(fp).unwrap();
-        file.write_all(req.as_slice()).unwrap();
-        
-        Ok(fp.to_string())
+        info.iter()
+            .find(|item| item.name == name)
+            .cloned()
+            .ok_or_else(|| TlsClientError::NoMatchingAsset(format!("release has no asset for {}", target)))
     }
-    
-    fn parse_for_file(info: Vec<AssetEntry>) -> Result<String, String> {
-        let os = match std::env::consts::OS {
-            "windows" => "-windows",
-            "macos" => "-darwin",
-            "linux" => "-linux",
-            _ => return Err(String::from("Invalid OS detected.")),
-        };
 
-        let arch = match std::env::consts::ARCH {
-            "x86" => "-32",
-            "x86_64" => if os == "-windows" { "-64" } else { "-amd64" },
-            "aarch64" => "-arm64",
-            "arm" => "-armv7",
-            _ => return Err(String::from("Invalid AARCH detected.")),
+    /// Look up the checksum of `asset_name` in a checksum file published with the release.
+    ///
+    /// Supports both `<asset>.sha256` files and combined `checksums.txt`/`SHA256SUMS` style files
+    /// of `<hex>  <filename>` lines. Returns `None` if the release publishes neither.
+    fn published_checksum(
+        client: &reqwest::blocking::Client,
+        options: &InitOptions,
+        release: &ReleaseInfo,
+        asset_name: &str,
+    ) -> Result<Option<String>, TlsClientError> {
+        let info = &release.assets;
+        let sidecar = format!("{}.sha256", asset_name);
+        let checksum_file = info.iter()
+            .find(|item| item.name == sidecar)
+            .or_else(|| info.iter().find(|item| {
+                let name = item.name.to_lowercase();
+                name.ends_with("checksums.txt") || name.contains("sha256sums")
+            }));
+
+        let checksum_file = match checksum_file {
+            Some(item) => item,
+            None => return Ok(None),
         };
 
-        let mut url = String::new();
-        for item in info {
-            let name = item.get_name();
-            if name.contains(os) && name.contains(arch) && !name.contains("xgo") {
-                url = item.get_browser_url();
+        let url = Self::asset_url(options, release, checksum_file);
+        let content = String::from_utf8_lossy(&Self::download_file(client, url)?).into_owned();
+        let checksum = content.lines().find_map(|line| {
+            let mut parts = line.split_whitespace();
+            let hash = parts.next()?;
+            match parts.next() {
+                Some(name) if name.trim_start_matches('*') == asset_name => Some(hash.to_string()),
+                None if checksum_file.name == sidecar => Some(hash.to_string()),
+                _ => None,
             }
+        });
+
+        match checksum {
+            Some(checksum) => Ok(Some(checksum)),
+            None => Err(TlsClientError::Download(format!("`{}` has no checksum for `{}`.", checksum_file.name, asset_name))),
         }
-        
-        if url.is_empty() {
-            return Err(String::from("No file detected."))
-        }
-        
-        Ok(url)
     }
     
-    fn get_latest_info() -> Result<Vec<AssetEntry>, String> {
-        let mut header_map = HeaderMap::new();
-        header_map.insert("host", HeaderValue::from_str("api.github.com").unwrap());
-        header_map.insert("user-agent", HeaderValue::from_str("rust_tls_client").unwrap());
-        
-        let client = reqwest::blocking::Client::new();
+    /// Fetch the GitHub-shaped release metadata from `{release_url}/releases/...`.
+    fn get_release_info(client: &reqwest::blocking::Client, options: &InitOptions) -> Result<ReleaseInfo, TlsClientError> {
+        let base = options.release_url();
+        let base = base.trim_end_matches('/');
+        let url = match options.version() {
+            Some(tag) => format!("{}/releases/tags/{}", base, tag),
+            None => format!("{}/releases/latest", base),
+        };
         
-        let request = client.get("https://api.github.com/repos/bogdanfinn/tls-client/releases/latest")
-            .headers(header_map)
+        let request = client.get(url)
             .send()
-            .map_err(|_| String::from("Failed to retrieve latest release."))
-            .unwrap();
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| TlsClientError::Download(format!("Failed to retrieve release: {}", e)))?;
         
-        let build: Value = request
+        let release: ReleaseInfo = request
             .json()
-            .map_err(|_| String::from("Failed to convert response to `Value`."))
-            .unwrap();
+            .map_err(|e| TlsClientError::Download(format!("Failed to deserialize release: {}", e)))?;
         
-        let assets = match build.get("assets") {
-            Some(val) => val.to_owned(),
-            None => return Err(String::from("Failed to extract asset data."))
-        };
-        
-        let object: Vec<AssetEntry> = serde_json::from_value(assets)
-            .map_err(|_| String::from("Failed to deserialize."))
-            .unwrap();
-        
-        Ok(object)
+        Ok(release)
     }
     
     pub fn path(&self) -> String {
-        self.0.clone()
+        self.0.path.display().to_string()
+    }
+
+    pub fn version(&self) -> String {
+        self.0.version.clone()
     }
 }
 
 
+/// Hex encoded SHA-256 digest of `bytes`.
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+fn verify_sha256(bytes: &[u8], expected: &str) -> Result<(), TlsClientError> {
+    let actual = sha256_hex(bytes);
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        return Err(TlsClientError::ChecksumMismatch {
+            expected: expected.trim().to_lowercase(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+
 #[derive(Deserialize)]
+struct ReleaseInfo {
+    tag_name: String,
+    assets: Vec<AssetEntry>,
+}
+
+
+#[derive(Deserialize, Clone)]
 struct AssetEntry {
     name: String,
     browser_download_url: String,
@@ -232,17 +501,287 @@ impl AssetEntry {
 }
 
 
-// 
-// mod tests {
-//     use super::*;
-// 
-//     #[test]
-//     fn test_get_latest() {
-//         let tcbd = TlsClientBinaryDownloader::new();
-//         
-//     }
-//     
-//     
-// 
-// 
-// }
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use lazy_static::lazy_static;
+    use uuid::Uuid;
+    use crate::client::TlsClient;
+    use super::*;
+
+    lazy_static! {
+        // Responses handed out by `fake_request` by id, with the url they answered
+        static ref FAKE_ALLOCATIONS: Mutex<HashMap<String, (usize, String)>> = Mutex::new(HashMap::new());
+        // Urls whose response was passed to `fake_free_memory`
+        static ref FAKE_FREED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    }
+
+    /// Stand-in for the `request` export. Urls containing `slow` take 200ms to answer and urls
+    /// containing `invalid` get a response which does not deserialize.
+    unsafe extern "C" fn fake_request(payload: *const c_char) -> *const c_char {
+        let payload: serde_json::Value = serde_json::from_str(&CStr::from_ptr(payload).to_string_lossy()).unwrap();
+        let url = payload["requestUrl"].as_str().unwrap_or_default().to_string();
+        if url.contains("slow") {
+            std::thread::sleep(Duration::from_millis(200));
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let response = match url.contains("invalid") {
+            true => serde_json::json!({ "id": id, "status": "not a number" }),
+            false => serde_json::json!({ "id": id, "sessionId": payload["sessionId"], "status": 200, "target": url }),
+        };
+        let ptr = CString::new(response.to_string()).unwrap().into_raw();
+        FAKE_ALLOCATIONS.lock().unwrap().insert(id, (ptr as usize, url));
+        ptr
+    }
+
+    unsafe extern "C" fn fake_free_memory(id: *const c_char) {
+        let id = CStr::from_ptr(id).to_string_lossy().into_owned();
+        if let Some((ptr, url)) = FAKE_ALLOCATIONS.lock().unwrap().remove(&id) {
+            drop(CString::from_raw(ptr as *mut c_char));
+            FAKE_FREED.lock().unwrap().push(url);
+        }
+    }
+
+    /// Library handle backed by the fake exports above.
+    pub(crate) fn fake_library() -> Arc<TlsClientSharedMethods> {
+        Arc::new(TlsClientSharedMethods::with_exports(fake_request, fake_free_memory))
+    }
+
+    /// Wait up to two seconds for the response to `url` to be freed.
+    pub(crate) fn wait_until_freed(url: &str) -> bool {
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(2) {
+            if FAKE_FREED.lock().unwrap().iter().any(|freed| freed == url) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    const LIBRARY_BYTES: &[u8] = b"not really a shared library";
+
+    type RequestLog = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+    /// Minimal HTTP server standing in for the GitHub API and release downloads. Records the
+    /// request line and `authorization` header of every request.
+    fn serve(routes: HashMap<String, Vec<u8>>) -> (String, RequestLog) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        let log = seen.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+
+                let mut auth = None;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((key, value)) = line.split_once(':') {
+                        if key.eq_ignore_ascii_case("authorization") {
+                            auth = Some(value.trim().to_string());
+                        }
+                    }
+                }
+                log.lock().unwrap().push((path.clone(), auth));
+
+                let (status, body) = match routes.get(&path) {
+                    Some(body) => ("200 OK", body.clone()),
+                    None => ("404 Not Found", vec![]),
+                };
+                let head = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+                let _ = stream.write_all(head.as_bytes());
+                let _ = stream.write_all(&body);
+            }
+        });
+
+        (base, seen)
+    }
+
+    /// Routes for a `v1.7.2` release whose only library asset targets the current platform.
+    fn release_routes() -> (HashMap<String, Vec<u8>>, String) {
+        let target = TargetPlatform::current(Libc::detect()).unwrap();
+        let libc = match target.libc {
+            Some(Libc::Musl) => "alpine-",
+            Some(Libc::Glibc) => "ubuntu-",
+            Some(Libc::Unknown) | None => "",
+        };
+        let arch = match (&target.os, &target.arch) {
+            (crate::asset::AssetOs::Windows, crate::asset::AssetArch::Amd64) => String::from("64"),
+            (crate::asset::AssetOs::Windows, crate::asset::AssetArch::X86) => String::from("32"),
+            (_, arch) => arch.to_string(),
+        };
+        let ext = match std::env::consts::OS {
+            "windows" => "dll",
+            "macos" => "dylib",
+            _ => "so",
+        };
+        let name = format!("tls-client-{}-{}{}-1.7.2.{}", target.os, libc, arch, ext);
+
+        let release = serde_json::json!({
+            "tag_name": "v1.7.2",
+            "assets": [
+                { "name": name, "browser_download_url": "https://github.invalid/unreachable" },
+            ],
+        });
+
+        let mut routes = HashMap::new();
+        routes.insert(String::from("/api/releases/tags/v1.7.2"), serde_json::to_vec(&release).unwrap());
+        routes.insert(format!("/download/v1.7.2/{}", name), LIBRARY_BYTES.to_vec());
+        (routes, name)
+    }
+
+    fn mirror_options(base: &str) -> InitOptions {
+        InitOptions::new()
+            .with_release_url(format!("{}/api", base))
+            .with_download_url(format!("{}/download", base))
+            .with_download_header("Authorization", "Bearer secret")
+            .with_version("v1.7.2")
+            .with_cache_dir(std::env::temp_dir().join(format!("rust_tls_client_test_{}", Uuid::new_v4())))
+    }
+
+    #[test]
+    fn test_download_from_mirror() {
+        let (routes, name) = release_routes();
+        let (base, seen) = serve(routes);
+        let options = mirror_options(&base);
+
+        let bf = TlsClientBinaryDownloader::new(&options).unwrap();
+        assert_eq!(std::fs::read(bf.path()).unwrap(), LIBRARY_BYTES);
+
+        let seen = seen.lock().unwrap();
+        let paths: Vec<&str> = seen.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, vec![String::from("/api/releases/tags/v1.7.2"), format!("/download/v1.7.2/{}", name)]);
+        assert!(seen.iter().all(|(_, auth)| auth.as_deref() == Some("Bearer secret")));
+
+        // Later loads are served from the cache without touching the mirror
+        assert_eq!(TlsClientBinaryDownloader::cached(&options).unwrap().path(), bf.path());
+
+        std::fs::remove_dir_all(options.cache_dir().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_download_checksum_mismatch_is_not_cached() {
+        let (routes, _) = release_routes();
+        let (base, _) = serve(routes);
+        let options = mirror_options(&base).with_sha256(sha256_hex(b"something else"));
+
+        assert!(matches!(
+            TlsClientBinaryDownloader::new(&options),
+            Err(TlsClientError::ChecksumMismatch { .. }),
+        ));
+        assert!(TlsClientBinaryDownloader::cached(&options.with_sha256(sha256_hex(LIBRARY_BYTES))).is_none());
+    }
+
+    #[test]
+    fn test_methods_are_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<TlsClientSharedMethods>();
+        assert_send_sync::<RequestPayload>();
+    }
+
+    #[test]
+    fn test_library_strings_are_freed() {
+        let methods = fake_library();
+        let client = TlsClient::default().set_backend(methods.clone());
+
+        let url = format!("https://example.com/{}", Uuid::new_v4());
+        assert_eq!(client.get(&url).send().unwrap().get_status(), 200);
+        assert!(wait_until_freed(&url));
+        assert_eq!(methods.outstanding_allocations(), 0);
+
+        // The guard frees a response which fails to deserialize
+        let url = format!("https://example.com/invalid/{}", Uuid::new_v4());
+        assert!(matches!(client.get(&url).send(), Err(TlsClientError::Ffi { .. })));
+        assert!(wait_until_freed(&url));
+        assert_eq!(methods.outstanding_allocations(), 0);
+    }
+
+    #[test]
+    fn test_abandoned_request_is_freed() {
+        let methods = fake_library();
+        let client = TlsClient::default().set_backend(methods.clone());
+
+        let url = format!("https://example.com/slow/{}", Uuid::new_v4());
+        let res = client.get(&url).deadline(Duration::from_millis(20)).send();
+        assert!(matches!(res, Err(TlsClientError::Timeout(_))));
+
+        // The call carries on after the caller gave up and frees its response
+        assert!(wait_until_freed(&url));
+        assert_eq!(methods.outstanding_allocations(), 0);
+    }
+
+    #[test]
+    fn test_response_id() {
+        assert_eq!(response_id(r#"{"id":"abc","cookies":[]}"#).as_deref(), Some("abc"));
+        assert_eq!(response_id(r#" {"id":"abc","success":true} "#).as_deref(), Some("abc"));
+        assert_eq!(response_id(r#"{"id":""}"#), None);
+        assert_eq!(response_id("not json"), None);
+    }
+
+    #[test]
+    fn test_verify_sha256() {
+        let digest = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(verify_sha256(b"hello", digest).is_ok());
+        assert!(verify_sha256(b"hello", &digest.to_uppercase()).is_ok());
+
+        match verify_sha256(b"hello!", digest) {
+            Err(TlsClientError::ChecksumMismatch { expected, actual }) => {
+                assert_eq!(expected, digest);
+                assert_ne!(actual, digest);
+            }
+            _ => panic!("expected a checksum mismatch"),
+        }
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    #[test]
+    fn test_missing_export_is_named() {
+        // Loads fine, but is not tls-client
+        match TlsClientSharedMethods::new("libc.so.6") {
+            Err(TlsClientError::Ffi { export, .. }) => assert_eq!(export, "request"),
+            other => panic!("expected a missing export error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn test_parse_for_file_respects_libc() {
+        let assets: Vec<AssetEntry> = [
+            "tls-client-linux-alpine-amd64-1.7.2.so",
+            "tls-client-linux-alpine-amd64-1.7.2.h",
+            "tls-client-linux-ubuntu-amd64-1.7.2.so",
+            "tls-client-linux-ubuntu-amd64-1.7.2.h",
+            "tls-client-xgo-1.7.2-linux-amd64.so",
+        ].iter()
+            .map(|name| AssetEntry { name: name.to_string(), browser_download_url: format!("https://example.com/{}", name) })
+            .collect();
+
+        let musl = TlsClientBinaryDownloader::parse_for_file(&assets, Some(Libc::Musl)).unwrap();
+        assert_eq!(musl.name, "tls-client-linux-alpine-amd64-1.7.2.so");
+
+        let glibc = TlsClientBinaryDownloader::parse_for_file(&assets, Some(Libc::Glibc)).unwrap();
+        assert_eq!(glibc.name, "tls-client-linux-ubuntu-amd64-1.7.2.so");
+
+        let glibc_only: Vec<AssetEntry> = assets.into_iter().filter(|item| !item.name.contains("alpine")).collect();
+        assert!(matches!(
+            TlsClientBinaryDownloader::parse_for_file(&glibc_only, Some(Libc::Musl)),
+            Err(TlsClientError::NoMatchingAsset(_)),
+        ));
+    }
+}
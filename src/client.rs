@@ -1,7 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
 use serde::{Deserialize, Serialize};
+use reqwest::Url;
+use serde_json::json;
+use lazy_static::lazy_static;
 use uuid::Uuid;
-use crate::request::{RequestPayload};
+use crate::backend::TlsBackend;
+use crate::cookie::{parse_cookies, unix_now, Cookie, CookieFormat};
+use crate::error::TlsClientError;
+use crate::loader::{loaded_backend, shared_backend};
+use crate::profile::RequestContext;
+use crate::request::{is_token, RequestPayload};
+use crate::pool::WorkerPool;
 use crate::types::{AeadId, ClientIdentifier, DelegatedCredential, H2Setting, KdfId, KeyShareCurve, SignatureAlgorithm, SupportedVersion};
 
 
@@ -26,6 +37,16 @@ pub struct TlsClient {
     // Request parameters
     request_url:                        Option<String>,
     request_method:                     Option<String>,
+
+    // Transport, `None` uses the shared library
+    #[serde(skip)]
+    backend:                            Option<Arc<dyn TlsBackend>>,
+    // Leave the session alive in the library when dropped
+    #[serde(skip)]
+    detached:                           bool,
+    // Send requests without the identifier's browser headers
+    #[serde(skip)]
+    without_default_headers:            bool,
 }
 
 
@@ -41,10 +62,20 @@ impl TlsClient {
             tls_client_identifier: self.tls_client_identifier.clone(),
             with_random_tls_extension_order: self.with_random_tls_extension_order,
             custom_tls_client: self.custom_tls_client.clone(),
-            request_url: Some(url),
+            request_url: None,
             request_method: Some(method),
+            backend: None,
+            // Only a serialization template, the session belongs to `self`
+            detached: true,
+            without_default_headers: self.without_default_headers,
         };
-        serde_json::from_value(serde_json::to_value(tc).unwrap()).unwrap()
+        let mut payload: RequestPayload = serde_json::from_value(serde_json::to_value(tc).unwrap()).unwrap();
+        payload.backend = self.backend.clone();
+        payload.set_url(&url);
+        if !self.without_default_headers {
+            payload.apply_profile(RequestContext::Navigation);
+        }
+        payload
     }
 
     pub fn new(client_identifier: ClientIdentifier, random_tls_order: bool) -> TlsClient {
@@ -59,6 +90,9 @@ impl TlsClient {
             proxy_url: None,
             request_url: None,
             request_method: None,
+            backend: None,
+            detached: false,
+            without_default_headers: false,
         }
     }
 
@@ -74,6 +108,9 @@ impl TlsClient {
             proxy_url: None,
             request_url: None,
             request_method: None,
+            backend: None,
+            detached: false,
+            without_default_headers: false,
         }
     }
 
@@ -102,6 +139,77 @@ impl TlsClient {
         self
     }
 
+    /// Whether requests start with the headers the identifier's browser sends (see
+    /// `ClientIdentifier::default_headers`). Enabled by default.
+    pub fn set_default_headers(mut self, enabled: bool) -> Self {
+        self.without_default_headers = !enabled;
+        self
+    }
+
+    /// Route this client's requests through `backend` instead of the shared library.
+    pub fn set_backend(mut self, backend: Arc<dyn TlsBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Keep the session alive in the library after this client is dropped, instead of destroying
+    /// it. It is still released by `destroy_all` or `shutdown`.
+    pub fn into_detached(mut self) -> Self {
+        self.detached = true;
+        self
+    }
+
+    pub(crate) fn session(&self) -> Session {
+        Session::new(self.session_id.clone(), self.backend.clone())
+    }
+
+    /// Cookies the session holds for `url`, as the JSON returned by the library.
+    pub fn get_cookies_from_session(&self, url: &str) -> Result<String, TlsClientError> {
+        self.session().get_cookies(url)
+    }
+
+    /// Add `cookies` (a JSON array of cookie objects) to the session for `url`.
+    pub fn add_cookies_to_session(&self, url: &str, cookies: serde_json::Value) -> Result<String, TlsClientError> {
+        self.session().add_cookies(url, &cookies)
+    }
+
+    /// Cookies the session would send to `url`.
+    pub fn cookies(&self, url: &str) -> Result<Vec<Cookie>, TlsClientError> {
+        self.session().cookies(url)
+    }
+
+    /// Add `cookies` to the session as if they were set by a response from `url`.
+    pub fn add_cookies(&self, url: &str, cookies: &[Cookie]) -> Result<(), TlsClientError> {
+        self.session().add_typed_cookies(url, cookies)
+    }
+
+    /// Save the cookies the session holds for `urls` to `path`, returning how many were written.
+    ///
+    /// The library may only report the name and value of each cookie, in which case it is saved as
+    /// a host-only session cookie of the url it was read for.
+    pub fn save_cookies<P: AsRef<Path>>(&self, path: P, urls: &[&str], format: CookieFormat) -> Result<usize, TlsClientError> {
+        let path = path.as_ref();
+        let cookies = self.session().export_cookies(urls)?;
+        std::fs::write(path, format.serialize(&cookies)?)
+            .map_err(|e| TlsClientError::CookieJar(format!("Failed to write `{}`: {}", path.display(), e)))?;
+        Ok(cookies.len())
+    }
+
+    /// Add the cookies saved in `path` to the session, skipping expired ones. Returns how many
+    /// were added.
+    pub fn load_cookies<P: AsRef<Path>>(&self, path: P, format: CookieFormat) -> Result<usize, TlsClientError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| TlsClientError::CookieJar(format!("Failed to read `{}`: {}", path.display(), e)))?;
+        self.session().import_cookies(format.parse(&contents)?)
+    }
+
+    /// Drop the session inside the library, releasing its cookies and connections. A later request
+    /// starts a new session under the same id.
+    pub fn destroy_session(&self) -> Result<String, TlsClientError> {
+        self.session().destroy()
+    }
+
     pub fn get(&self, url: &str) -> RequestPayload {
         self.build_for_request("GET".to_string(), url.to_string())
     }
@@ -110,6 +218,169 @@ impl TlsClient {
         self.build_for_request("POST".to_string(), url.to_string())
     }
 
+    pub fn put(&self, url: &str) -> RequestPayload {
+        self.build_for_request("PUT".to_string(), url.to_string())
+    }
+
+    pub fn patch(&self, url: &str) -> RequestPayload {
+        self.build_for_request("PATCH".to_string(), url.to_string())
+    }
+
+    pub fn delete(&self, url: &str) -> RequestPayload {
+        self.build_for_request("DELETE".to_string(), url.to_string())
+    }
+
+    /// HEAD request, which is sent without a body and whose response has `body` set to `None`.
+    pub fn head(&self, url: &str) -> RequestPayload {
+        self.build_for_request("HEAD".to_string(), url.to_string())
+    }
+
+    pub fn options(&self, url: &str) -> RequestPayload {
+        self.build_for_request("OPTIONS".to_string(), url.to_string())
+    }
+
+    /// Request with any method, e.g. a `reqwest::Method`, an `http::Method` or a `&str`. Methods
+    /// are case-sensitive, and one which is not a valid RFC 7230 token fails on `send` with
+    /// `TlsClientError::InvalidMethod`.
+    pub fn request<M: AsRef<str>>(&self, method: M, url: &str) -> RequestPayload {
+        let method = method.as_ref();
+        let mut payload = self.build_for_request(method.to_string(), url.to_string());
+        if !is_token(method) {
+            payload.fail(TlsClientError::InvalidMethod(format!("`{}` is not a valid token", method)));
+        }
+        payload
+    }
+
+}
+
+lazy_static! {
+    // Destroys the sessions of dropped clients, one at a time
+    static ref TEARDOWN: WorkerPool = WorkerPool::new(1);
+}
+
+/// Destroys the session in the library, releasing its cookies and connections, unless the client
+/// was detached. The call is queued on a background thread so dropping never blocks. Nothing is
+/// loaded for this, so a client of a library which was never loaded (or already shut down) is
+/// dropped without a call.
+impl Drop for TlsClient {
+    fn drop(&mut self) {
+        if self.detached {
+            return;
+        }
+
+        let backend = match &self.backend {
+            Some(backend) => backend.clone(),
+            None => match loaded_backend() {
+                Some(backend) => backend,
+                None => return,
+            },
+        };
+        let payload = json!({ "sessionId": self.session_id }).to_string();
+        TEARDOWN.spawn(move || {
+            let _ = backend.release_session(payload);
+        });
+    }
+}
+
+/// The library session of a `TlsClient`, detached from the client so it can be moved to another
+/// thread.
+#[derive(Debug, Clone)]
+pub(crate) struct Session {
+    session_id: String,
+    backend:    Option<Arc<dyn TlsBackend>>,
+}
+
+impl Session {
+    pub fn new(session_id: String, backend: Option<Arc<dyn TlsBackend>>) -> Self {
+        Self { session_id, backend }
+    }
+
+    fn backend(&self) -> Result<Arc<dyn TlsBackend>, TlsClientError> {
+        match &self.backend {
+            Some(backend) => Ok(backend.clone()),
+            None => shared_backend(),
+        }
+    }
+
+    pub fn get_cookies(&self, url: &str) -> Result<String, TlsClientError> {
+        let payload = json!({ "sessionId": self.session_id, "url": url });
+        self.backend()?.get_cookies_from_session(payload.to_string())
+    }
+
+    pub fn add_cookies<C: Serialize + ?Sized>(&self, url: &str, cookies: &C) -> Result<String, TlsClientError> {
+        let payload = json!({ "sessionId": self.session_id, "url": url, "cookies": cookies });
+        self.backend()?.add_cookies_to_session(payload.to_string())
+    }
+
+    pub fn cookies(&self, url: &str) -> Result<Vec<Cookie>, TlsClientError> {
+        parse_cookies(&self.get_cookies(url)?, "getCookiesFromSession")
+    }
+
+    pub fn add_typed_cookies(&self, url: &str, cookies: &[Cookie]) -> Result<(), TlsClientError> {
+        parse_cookies(&self.add_cookies(url, cookies)?, "addCookiesToSession").map(|_| ())
+    }
+
+    /// Cookies for `urls`, with the domain and path needed to restore them filled in.
+    pub fn export_cookies(&self, urls: &[&str]) -> Result<Vec<Cookie>, TlsClientError> {
+        let now = unix_now();
+        let mut exported: Vec<Cookie> = vec![];
+        for url in urls {
+            let host = Url::parse(url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .ok_or_else(|| TlsClientError::CookieJar(format!("Invalid url `{}`", url)))?;
+
+            for mut cookie in self.cookies(url)? {
+                // The library strips the leading `.` of domain cookies
+                cookie.domain = match cookie.domain.trim_start_matches('.') {
+                    "" => host.clone(),
+                    domain => format!(".{}", domain),
+                };
+                if cookie.path.is_empty() {
+                    cookie.path = String::from("/");
+                }
+                // `Max-Age` is relative to when the cookie was set, which a file cannot express
+                if cookie.expires.is_none() && cookie.max_age > 0 {
+                    cookie.expires = Some(now + cookie.max_age);
+                    cookie.max_age = 0;
+                }
+
+                let seen = exported.iter().any(|other| {
+                    (&other.domain, &other.path, &other.name) == (&cookie.domain, &cookie.path, &cookie.name)
+                });
+                if !seen {
+                    exported.push(cookie);
+                }
+            }
+        }
+        Ok(exported)
+    }
+
+    /// Add unexpired `cookies` from a cookie file, grouped by the url they apply to.
+    pub fn import_cookies(&self, cookies: Vec<Cookie>) -> Result<usize, TlsClientError> {
+        let now = unix_now();
+        let mut by_origin: BTreeMap<String, Vec<Cookie>> = BTreeMap::new();
+        for mut cookie in cookies.into_iter().filter(|cookie| !cookie.is_expired(now)) {
+            let origin = cookie.origin();
+            // Host-only cookies are added without a domain, so the library binds them to the url's host
+            if !cookie.domain.starts_with('.') {
+                cookie.domain.clear();
+            }
+            by_origin.entry(origin).or_default().push(cookie);
+        }
+
+        let mut imported = 0;
+        for (origin, cookies) in by_origin {
+            self.add_typed_cookies(&origin, &cookies)?;
+            imported += cookies.len();
+        }
+        Ok(imported)
+    }
+
+    pub fn destroy(&self) -> Result<String, TlsClientError> {
+        let payload = json!({ "sessionId": self.session_id });
+        self.backend()?.destroy_session(payload.to_string())
+    }
 }
 
 impl Default for TlsClient {
@@ -253,26 +524,79 @@ pub struct PriorityParam {
 }
 
 
-mod tests {
+pub(crate) mod tests {
     #[cfg(test)]
     use std::io::Read;
     #[cfg(test)]
-    use reqwest::header::{ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, CONTENT_TYPE, USER_AGENT, HeaderMap, HeaderValue};
-    #[cfg(test)]
     use super::*;
 
+    #[cfg(test)]
+    use serde_json::Value;
+    #[cfg(test)]
+    use crate::backend::tests::{fake_client, FakeBackend};
+
+    /// Wait until the sessions of the clients dropped so far are destroyed.
+    #[cfg(test)]
+    pub(crate) fn wait_for_teardown() {
+        TEARDOWN.execute(|| ()).recv().unwrap();
+    }
+
     #[test]
-    fn test_get() {
-        let mut hm = HeaderMap::new();
-        hm.insert(ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.9"));
-        hm.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/105.0.0.0 Safari/537.36"));
-        hm.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate, br"));
-        hm.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("de-DE,de;q=0.9,en-US;q=0.8,en;q=0.7"));
+    fn test_drop_destroys_session() {
+        let (backend, client) = fake_client(FakeBackend::default());
+        let session_id = client.session_id.clone();
+
+        client.get("https://example.com").send().unwrap();
+        assert!(backend.destroyed.lock().unwrap().is_empty());
+
+        drop(client);
+        wait_for_teardown();
+        assert_eq!(*backend.destroyed.lock().unwrap(), vec![session_id]);
+    }
+
+    #[test]
+    fn test_detached_session_survives_drop() {
+        let (backend, client) = fake_client(FakeBackend::default());
+        let client = client.into_detached();
+        client.get("https://example.com").send().unwrap();
+
+        drop(client);
+        wait_for_teardown();
+        assert!(backend.destroyed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_methods() {
+        let (backend, client) = fake_client(FakeBackend::default());
+
+        client.put("https://example.com").send().unwrap();
+        client.patch("https://example.com").send().unwrap();
+        client.delete("https://example.com").send().unwrap();
+        client.options("https://example.com").send().unwrap();
+        client.request(reqwest::Method::from_bytes(b"PROPFIND").unwrap(), "https://example.com").send().unwrap();
+        client.request("M-SEARCH", "https://example.com").send().unwrap();
+        assert!(client.head("https://example.com").send().unwrap().body.is_none());
 
+        let methods: Vec<Value> = backend.payloads.lock().unwrap().iter().map(|p| p["requestMethod"].clone()).collect();
+        assert_eq!(methods, ["PUT", "PATCH", "DELETE", "OPTIONS", "PROPFIND", "M-SEARCH", "HEAD"]);
+    }
+
+    #[test]
+    fn test_invalid_requests_are_not_sent() {
+        let (backend, client) = fake_client(FakeBackend::default());
+
+        let res = client.request("GET /admin", "https://example.com").send();
+        assert!(matches!(res, Err(TlsClientError::InvalidMethod(_))));
+        let res = client.head("https://example.com").body("data").send();
+        assert!(matches!(res, Err(TlsClientError::InvalidRequest(_))));
+        assert!(backend.payloads.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get() {
         let client = TlsClient::new(ClientIdentifier::Chrome105, false);
 
         let req = &client.get("https://microsoft.com")
-            .headers(hm)
             .send()
             .unwrap();
 
@@ -281,18 +605,10 @@ mod tests {
     
     #[test]
     fn test_post() {
-        let mut hm = HeaderMap::new();
-        hm.insert(ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.9"));
-        hm.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/105.0.0.0 Safari/537.36"));
-        hm.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate, br"));
-        hm.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded"));
-        hm.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("de-DE,de;q=0.9,en-US;q=0.8,en;q=0.7"));
-
         let client = TlsClient::new(ClientIdentifier::Chrome105, false);
         
         let req = client.post("https://www.toptal.com/developers/postbin/1711461823032-1978890220634")
-            .headers(hm)
-            .body("foo=bar&baz=foo")
+            .form(&[("foo", "bar"), ("baz", "foo")])
             .send()
             .unwrap();
 
@@ -301,16 +617,9 @@ mod tests {
     
     #[test]
     fn test_get_image() {
-        let mut hm = HeaderMap::new();
-        hm.insert(ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.9"));
-        hm.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/105.0.0.0 Safari/537.36"));
-        hm.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate, br"));
-        hm.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("de-DE,de;q=0.9,en-US;q=0.8,en;q=0.7"));
-
         let client = TlsClient::new(ClientIdentifier::Chrome105, false);
         
         let req = client.get("https://avatars.githubusercontent.com/u/17678241?v=4")
-            .headers(hm)
             .byte_response() // Set response type to bytes
             .send()
             .unwrap();
@@ -322,12 +631,6 @@ mod tests {
 
     #[test]
     fn test_post_image() {
-        let mut hm = HeaderMap::new();
-        hm.insert(ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.9"));
-        hm.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/105.0.0.0 Safari/537.36"));
-        hm.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate, br"));
-        hm.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("de-DE,de;q=0.9,en-US;q=0.8,en;q=0.7"));
-
         let client = TlsClient::new(ClientIdentifier::Chrome105, false);
         
         // Load image and convert to base64 string
@@ -338,7 +641,6 @@ mod tests {
         println!("{}", b64_file);
 
         let req = client.post("https://www.toptal.com/developers/postbin/1711492583368-7330834681633")
-            .headers(hm)
             .body(b64_file)
             .byte_request()
             .send()
@@ -0,0 +1,327 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use crate::error::TlsClientError;
+
+
+/// A cookie held by a library session, in the JSON shape used by `getCookiesFromSession` and
+/// `addCookiesToSession`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cookie {
+    pub name:       String,
+    pub value:      String,
+    /// Empty for a host-only cookie of the url it was set for.
+    #[serde(default)]
+    pub domain:     String,
+    #[serde(default)]
+    pub path:       String,
+    /// Expiry as a unix timestamp in seconds, `None` for a session cookie.
+    #[serde(default, deserialize_with = "deserialize_expires", skip_serializing_if = "Option::is_none")]
+    pub expires:    Option<i64>,
+    /// `Max-Age` in seconds, 0 if unset and negative to delete the cookie.
+    #[serde(default)]
+    pub max_age:    i64,
+    #[serde(default)]
+    pub secure:     bool,
+    #[serde(default)]
+    pub http_only:  bool,
+    #[serde(default, deserialize_with = "deserialize_same_site", skip_serializing_if = "Option::is_none")]
+    pub same_site:  Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new<N: Into<String>, V: Into<String>>(name: N, value: V) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the cookie expired at unix time `now`, or is marked for deletion.
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.max_age < 0 || self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Url to add the cookie for, from the domain and path recorded in a cookie file.
+    pub(crate) fn origin(&self) -> String {
+        let scheme = if self.secure { "https" } else { "http" };
+        let path = if self.path.is_empty() { "/" } else { &self.path };
+        format!("{}://{}{}", scheme, self.domain.trim_start_matches('.'), path)
+    }
+}
+
+
+const NETSCAPE_HEADER: &str = "# Netscape HTTP Cookie File";
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+/// File formats for `TlsClient::save_cookies` and `TlsClient::load_cookies`.
+///
+/// Both record the domain of every cookie, following the `cookies.txt` convention: a leading `.`
+/// marks a cookie sent to subdomains as well, a bare host a host-only cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieFormat {
+    /// A JSON array of cookies in the upstream shape, see `Cookie`.
+    Json,
+    /// The Netscape `cookies.txt` format read and written by curl and browser extensions.
+    Netscape,
+}
+
+impl CookieFormat {
+    pub fn serialize(&self, cookies: &[Cookie]) -> Result<String, TlsClientError> {
+        match self {
+            CookieFormat::Json => serde_json::to_string_pretty(cookies)
+                .map_err(|e| TlsClientError::CookieJar(format!("Failed to serialize cookies: {}", e))),
+            CookieFormat::Netscape => {
+                let mut contents = format!("{}\n", NETSCAPE_HEADER);
+                for cookie in cookies {
+                    contents.push_str(&format!(
+                        "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                        if cookie.http_only { HTTP_ONLY_PREFIX } else { "" },
+                        cookie.domain,
+                        netscape_bool(cookie.domain.starts_with('.')),
+                        if cookie.path.is_empty() { "/" } else { &cookie.path },
+                        netscape_bool(cookie.secure),
+                        cookie.expires.unwrap_or(0),
+                        cookie.name,
+                        cookie.value,
+                    ));
+                }
+                Ok(contents)
+            }
+        }
+    }
+
+    pub fn parse(&self, contents: &str) -> Result<Vec<Cookie>, TlsClientError> {
+        match self {
+            CookieFormat::Json => serde_json::from_str(contents)
+                .map_err(|e| TlsClientError::CookieJar(format!("Invalid cookie file: {}", e))),
+            CookieFormat::Netscape => contents.lines()
+                .enumerate()
+                .filter(|(_, line)| {
+                    let line = line.trim();
+                    !line.is_empty() && (!line.starts_with('#') || line.starts_with(HTTP_ONLY_PREFIX))
+                })
+                .map(|(i, line)| parse_netscape_line(line)
+                    .ok_or_else(|| TlsClientError::CookieJar(format!("Invalid cookie on line {}: `{}`", i + 1, line))))
+                .collect(),
+        }
+    }
+}
+
+fn netscape_bool(value: bool) -> &'static str {
+    if value { "TRUE" } else { "FALSE" }
+}
+
+fn parse_netscape_line(line: &str) -> Option<Cookie> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (http_only, line) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+
+    let fields: Vec<&str> = line.split('\t').collect();
+    let [domain, include_subdomains, path, secure, expires, name, value] = fields.as_slice() else {
+        return None;
+    };
+
+    let host = domain.trim_start_matches('.');
+    Some(Cookie {
+        name: name.to_string(),
+        value: value.to_string(),
+        domain: match include_subdomains.eq_ignore_ascii_case("TRUE") {
+            true => format!(".{}", host),
+            false => host.to_string(),
+        },
+        path: path.to_string(),
+        expires: Some(expires.parse::<i64>().ok()?).filter(|expires| *expires > 0),
+        secure: secure.eq_ignore_ascii_case("TRUE"),
+        http_only,
+        ..Default::default()
+    })
+}
+
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SameSite {
+    Lax,
+    Strict,
+    None,
+}
+
+
+/// Go encodes a zero `time.Time` as a large negative timestamp, which means the cookie has no expiry.
+fn deserialize_expires<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    Ok(Option::<i64>::deserialize(deserializer)?.filter(|expires| *expires > 0))
+}
+
+/// Accept the attribute name as well as Go's numeric `http.SameSite` modes.
+fn deserialize_same_site<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<SameSite>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Name(String),
+        Mode(i64),
+    }
+
+    Ok(match Option::<Raw>::deserialize(deserializer)? {
+        Some(Raw::Name(name)) => match name.to_ascii_lowercase().as_str() {
+            "lax" => Some(SameSite::Lax),
+            "strict" => Some(SameSite::Strict),
+            "none" => Some(SameSite::None),
+            _ => None,
+        },
+        Some(Raw::Mode(2)) => Some(SameSite::Lax),
+        Some(Raw::Mode(3)) => Some(SameSite::Strict),
+        Some(Raw::Mode(4)) => Some(SameSite::None),
+        _ => None,
+    })
+}
+
+/// Cookies in a `getCookiesFromSession` or `addCookiesToSession` response. The library reports
+/// failures as a response with a `body` and no `cookies`.
+pub(crate) fn parse_cookies(response: &str, export: &str) -> Result<Vec<Cookie>, TlsClientError> {
+    let invalid = |reason: String| TlsClientError::Ffi { export: export.to_string(), reason };
+    let mut response: Value = serde_json::from_str(response.trim())
+        .map_err(|e| invalid(format!("invalid response: {}", e)))?;
+
+    match response.get_mut("cookies").map(Value::take) {
+        Some(Value::Null) => Ok(vec![]),
+        Some(cookies) => serde_json::from_value(cookies).map_err(|e| invalid(format!("invalid cookies: {}", e))),
+        None => Err(invalid(response.get("body")
+            .and_then(Value::as_str)
+            .unwrap_or("response has no cookies")
+            .to_string())),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::tests::{fake_client, FakeBackend};
+    use super::*;
+
+    #[test]
+    fn test_upstream_shape() {
+        let response = r#"{"id":"abc","cookies":[
+            {"name":"sid","value":"1","path":"/","domain":".example.com","expires":1893456000,"maxAge":0,"secure":true,"httpOnly":true,"sameSite":3},
+            {"name":"pref","value":"dark","path":"","domain":"","expires":-62135596800,"maxAge":0,"secure":false,"httpOnly":false}
+        ]}"#;
+
+        let cookies = parse_cookies(response, "getCookiesFromSession").unwrap();
+        assert_eq!(cookies[0], Cookie {
+            domain: String::from(".example.com"),
+            path: String::from("/"),
+            expires: Some(1893456000),
+            secure: true,
+            http_only: true,
+            same_site: Some(SameSite::Strict),
+            ..Cookie::new("sid", "1")
+        });
+        assert_eq!(cookies[1], Cookie::new("pref", "dark"));
+
+        let serialized = serde_json::to_value(&cookies[0]).unwrap();
+        assert_eq!(serialized["httpOnly"], true);
+        assert_eq!(serialized["maxAge"], 0);
+        assert_eq!(serialized["sameSite"], "Strict");
+        assert_eq!(serde_json::from_value::<Cookie>(serialized).unwrap(), cookies[0]);
+    }
+
+    #[test]
+    fn test_error_response() {
+        let err = parse_cookies(r#"{"id":"abc","status":0,"body":"no session found"}"#, "getCookiesFromSession");
+        assert!(matches!(err, Err(TlsClientError::Ffi { reason, .. }) if reason == "no session found"));
+    }
+
+    #[test]
+    fn test_netscape_format() {
+        let contents = "# Netscape HTTP Cookie File\n\
+            # written by curl\n\
+            \n\
+            .example.com\tTRUE\t/\tTRUE\t1893456000\tsid\t1\n\
+            #HttpOnly_www.example.com\tFALSE\t/account\tFALSE\t0\tpref\tdark\r\n";
+
+        let cookies = CookieFormat::Netscape.parse(contents).unwrap();
+        assert_eq!(cookies, vec![
+            Cookie {
+                domain: String::from(".example.com"),
+                path: String::from("/"),
+                expires: Some(1893456000),
+                secure: true,
+                ..Cookie::new("sid", "1")
+            },
+            Cookie {
+                domain: String::from("www.example.com"),
+                path: String::from("/account"),
+                http_only: true,
+                ..Cookie::new("pref", "dark")
+            },
+        ]);
+        assert_eq!(cookies[0].origin(), "https://example.com/");
+        assert_eq!(cookies[1].origin(), "http://www.example.com/account");
+
+        let serialized = CookieFormat::Netscape.serialize(&cookies).unwrap();
+        assert_eq!(CookieFormat::Netscape.parse(&serialized).unwrap(), cookies);
+        assert!(CookieFormat::Netscape.parse("example.com\tTRUE\t/").is_err());
+    }
+
+    #[test]
+    fn test_save_and_load_cookies() {
+        let path = std::env::temp_dir().join(format!("rust_tls_client_cookies_{}.txt", uuid::Uuid::new_v4()));
+        let (_, source) = fake_client(FakeBackend::default());
+        source.add_cookies("https://www.example.com", &[
+            Cookie::new("sid", "1"),
+            Cookie { domain: String::from("example.com"), max_age: 3600, ..Cookie::new("pref", "dark") },
+        ]).unwrap();
+
+        for format in [CookieFormat::Json, CookieFormat::Netscape] {
+            assert_eq!(source.save_cookies(&path, &["https://www.example.com/"], format).unwrap(), 2);
+
+            let (_, restored) = fake_client(FakeBackend::default());
+            assert_eq!(restored.load_cookies(&path, format).unwrap(), 2);
+
+            let cookies = restored.cookies("https://www.example.com").unwrap();
+            let named = |name: &str| cookies.iter().find(|cookie| cookie.name == name).unwrap();
+            // Host-only cookies are added without a domain, `Max-Age` was turned into an expiry
+            assert_eq!(*named("sid"), Cookie { path: String::from("/"), ..Cookie::new("sid", "1") });
+            assert_eq!(named("pref").domain, ".example.com");
+            assert!(named("pref").expires.unwrap() > unix_now());
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_drops_expired_cookies() {
+        let path = std::env::temp_dir().join(format!("rust_tls_client_cookies_{}.json", uuid::Uuid::new_v4()));
+        let cookies = [
+            Cookie { domain: String::from("example.com"), expires: Some(unix_now() - 60), ..Cookie::new("old", "1") },
+            Cookie { domain: String::from("example.com"), max_age: -1, ..Cookie::new("deleted", "1") },
+            Cookie { domain: String::from("example.com"), ..Cookie::new("session", "1") },
+        ];
+        std::fs::write(&path, CookieFormat::Json.serialize(&cookies).unwrap()).unwrap();
+
+        let (backend, client) = fake_client(FakeBackend::default());
+        assert_eq!(client.load_cookies(&path, CookieFormat::Json).unwrap(), 1);
+        assert_eq!(backend.cookies.lock().unwrap()[0]["name"], "session");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_client_cookies_round_trip() {
+        let (_, client) = fake_client(FakeBackend::default());
+
+        let cookies = vec![Cookie::new("sid", "1"), Cookie { secure: true, ..Cookie::new("pref", "dark") }];
+        client.add_cookies("https://example.com", &cookies).unwrap();
+        assert_eq!(client.cookies("https://example.com").unwrap(), cookies);
+    }
+}
@@ -1,8 +1,45 @@
 use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TlsClientError {
     GeneralError(String),
+    /// Fetching the release metadata or the shared library failed.
+    Download(String),
+    /// The shared library could not be loaded.
+    LibraryLoad(String),
+    /// No usable library was found and downloading is not possible or not allowed.
+    LibraryNotFound(String),
+    /// The downloaded library did not match the expected SHA-256 digest.
+    ChecksumMismatch { expected: String, actual: String },
+    /// Reading or writing the binary cache failed.
+    Cache(String),
+    /// The release has no library built for this platform.
+    NoMatchingAsset(String),
+    /// Calling (or resolving) an export of the shared library failed.
+    Ffi { export: String, reason: String },
+    /// The loaded library does not support the requested operation.
+    Unsupported(String),
+    /// The helper process of a `ProcessBackend` could not be started or exited mid-call.
+    Helper(String),
+    /// No result arrived within the deadline.
+    Timeout(Duration),
+    /// The request method is not a valid RFC 7230 token.
+    InvalidMethod(String),
+    /// A header name is not a valid token, or its value is not visible ASCII.
+    InvalidHeader(String),
+    /// The request was built with options which cannot be sent, e.g. a body on a HEAD request.
+    InvalidRequest(String),
+    /// The url is malformed, not http(s), or its query could not be encoded.
+    InvalidUrl(String),
+    /// Reading, writing or parsing a cookie file failed.
+    CookieJar(String),
+    /// The request was cancelled through its `CancellationToken`.
+    Cancelled,
+    /// The request was not sent because this many calls given up through a deadline or
+    /// cancellation are still running in the library.
+    TooManyAbandoned(usize),
 }
 
 
@@ -10,6 +47,23 @@ impl Display for TlsClientError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self { 
             TlsClientError::GeneralError(msg) => write!(f, "{}", msg),
+            TlsClientError::Download(msg) => write!(f, "Download failed: {}", msg),
+            TlsClientError::LibraryLoad(msg) => write!(f, "Library load failed: {}", msg),
+            TlsClientError::LibraryNotFound(msg) => write!(f, "Library not found: {}", msg),
+            TlsClientError::ChecksumMismatch { expected, actual } => write!(f, "Checksum mismatch: expected sha256 {}, got {}", expected, actual),
+            TlsClientError::Cache(msg) => write!(f, "Cache error: {}", msg),
+            TlsClientError::NoMatchingAsset(msg) => write!(f, "No matching asset: {}", msg),
+            TlsClientError::Ffi { export, reason } => write!(f, "Call to `{}` failed: {}", export, reason),
+            TlsClientError::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
+            TlsClientError::Helper(msg) => write!(f, "Helper process failed: {}", msg),
+            TlsClientError::Timeout(deadline) => write!(f, "Timed out after {:?}", deadline),
+            TlsClientError::InvalidMethod(msg) => write!(f, "Invalid method: {}", msg),
+            TlsClientError::InvalidHeader(msg) => write!(f, "Invalid header: {}", msg),
+            TlsClientError::InvalidRequest(msg) => write!(f, "Invalid request: {}", msg),
+            TlsClientError::InvalidUrl(msg) => write!(f, "Invalid url: {}", msg),
+            TlsClientError::CookieJar(msg) => write!(f, "Cookie jar error: {}", msg),
+            TlsClientError::Cancelled => write!(f, "Cancelled"),
+            TlsClientError::TooManyAbandoned(count) => write!(f, "Too many abandoned calls: {} are still running", count),
         }
     }
 }
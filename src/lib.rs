@@ -1,13 +1,36 @@
+mod asset;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod backend;
+mod cache;
+mod cancel;
+mod capability;
 mod client;
+mod cookie;
 mod cffi;
 mod error;
+mod loader;
+mod pool;
+mod process;
+mod profile;
 mod types;
 mod request;
 mod response;
+#[cfg(feature = "vendored")]
+mod vendored;
 
+pub use asset::*;
+pub use backend::*;
+pub use cancel::*;
+pub use capability::*;
 pub use cffi::*;
 pub use client::*;
+pub use cookie::*;
 pub use error::*;
+pub use loader::*;
+pub use pool::*;
+pub use process::*;
+pub use profile::*;
 pub use types::*;
 pub use request::*;
 pub use response::*;
@@ -0,0 +1,618 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, PoisonError, RwLock};
+use std::time::Duration;
+use lazy_static::lazy_static;
+use crate::backend::TlsBackend;
+use crate::capability::{Capability, LibraryInfo};
+use crate::cffi::{TlsClientBinaryDownloader, TlsClientSharedMethods};
+use crate::error::TlsClientError;
+use crate::request::RequestPayload;
+use crate::response::RequestResponse;
+
+
+lazy_static! {
+    // Backend used by requests which were not given one explicitly. Populated by `init`, or
+    // lazily with the default options on first use.
+    static ref SHARED_BACKEND: RwLock<Option<Arc<TrackedBackend>>> = RwLock::new(None);
+}
+
+
+/// Environment variable holding the path of a tls-client library to load instead of downloading one.
+pub const LIBRARY_PATH_ENV: &str = "RUST_TLS_CLIENT_LIB";
+
+/// Environment variable which, when set to anything but `0`/`false`, forbids downloading the library.
+pub const OFFLINE_ENV: &str = "RUST_TLS_CLIENT_OFFLINE";
+
+
+/// Environment variable pinning the tls-client release tag to download, e.g. `v1.7.2`.
+pub const VERSION_ENV: &str = "RUST_TLS_CLIENT_VERSION";
+
+
+/// Environment variable overriding the base URL release metadata is fetched from.
+pub const RELEASE_URL_ENV: &str = "RUST_TLS_CLIENT_RELEASE_URL";
+
+/// Default base URL of the GitHub-shaped release API.
+pub const DEFAULT_RELEASE_URL: &str = "https://api.github.com/repos/bogdanfinn/tls-client";
+
+
+/// C library a Linux build of tls-client is linked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Libc {
+    /// glibc, published upstream as the `ubuntu` build
+    Glibc,
+    /// musl, published upstream as the `alpine` build
+    Musl,
+    /// A libc label in an asset name this crate does not recognise
+    Unknown,
+}
+
+impl Libc {
+    /// Libc to use on this system, `None` when not running on Linux.
+    ///
+    /// The `ubuntu`/`alpine` features take precedence, otherwise the libc loaded into this process
+    /// is detected at runtime, falling back to the one this crate was compiled for.
+    pub fn detect() -> Option<Libc> {
+        if std::env::consts::OS != "linux" {
+            return None;
+        }
+
+        match (cfg!(feature = "ubuntu"), cfg!(feature = "alpine")) {
+            (true, false) => return Some(Libc::Glibc),
+            (false, true) => return Some(Libc::Musl),
+            _ => {}
+        }
+
+        if let Ok(maps) = std::fs::read_to_string("/proc/self/maps") {
+            if maps.contains("ld-musl") || maps.contains("libc.musl") {
+                return Some(Libc::Musl);
+            }
+            if maps.contains("libc.so.6") {
+                return Some(Libc::Glibc);
+            }
+        }
+
+        match cfg!(target_env = "musl") {
+            true => Some(Libc::Musl),
+            false => Some(Libc::Glibc),
+        }
+    }
+}
+
+impl Display for Libc {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Libc::Glibc => write!(f, "glibc"),
+            Libc::Musl => write!(f, "musl"),
+            Libc::Unknown => write!(f, "unknown libc"),
+        }
+    }
+}
+
+
+/// Options controlling how the shared tls-client library is located and loaded by `init`.
+///
+/// The library is looked up in order: `library_path` (or `RUST_TLS_CLIENT_LIB`), the library
+/// embedded by the `vendored` feature, a previously cached download (which must match `sha256`
+/// unless the cache dir is private to this user), and finally the pinned (or latest) GitHub release
+/// unless offline mode is enabled.
+#[derive(Debug, Default, Clone)]
+pub struct InitOptions {
+    library_path:   Option<PathBuf>,
+    offline:        bool,
+    version:        Option<String>,
+    sha256:         Option<String>,
+    libc:           Option<Libc>,
+    release_url:    Option<String>,
+    download_url:   Option<String>,
+    headers:        Vec<(String, String)>,
+    cache_dir:      Option<PathBuf>,
+}
+
+impl InitOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the library from `path`, never touching the network.
+    pub fn with_library_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.library_path = Some(path.into());
+        self
+    }
+
+    /// Forbid downloading the library, failing if no local copy is found.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Pin the release tag to download instead of the latest release, e.g. `v1.7.2`.
+    pub fn with_version<S: Into<String>>(mut self, version: S) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Expected hex encoded SHA-256 of the downloaded library. A download with a different digest
+    /// fails with `TlsClientError::ChecksumMismatch` and is not loaded.
+    pub fn with_sha256<S: Into<String>>(mut self, sha256: S) -> Self {
+        self.sha256 = Some(sha256.into());
+        self
+    }
+
+    /// Override the detected libc used to pick the Linux build, see `Libc::detect`.
+    pub fn with_libc(mut self, libc: Libc) -> Self {
+        self.libc = Some(libc);
+        self
+    }
+
+    /// Fetch release metadata from a mirror instead of GitHub. The mirror must serve GitHub-shaped
+    /// JSON at `{url}/releases/latest` and `{url}/releases/tags/{tag}`.
+    pub fn with_release_url<S: Into<String>>(mut self, url: S) -> Self {
+        self.release_url = Some(url.into());
+        self
+    }
+
+    /// Download assets from `{url}/{tag}/{asset name}` instead of the `browser_download_url`
+    /// given in the release metadata.
+    pub fn with_download_url<S: Into<String>>(mut self, url: S) -> Self {
+        self.download_url = Some(url.into());
+        self
+    }
+
+    /// Send an extra header, e.g. `Authorization`, with the metadata and asset requests.
+    pub fn with_download_header<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.headers.push((key.into(), value.into()));
+        self
+    }
+
+    /// Store downloaded binaries under `dir` instead of the per-user cache dir. On unix `dir` must
+    /// be owned by the current user and not writable by others.
+    pub fn with_cache_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    fn library_path(&self) -> Option<PathBuf> {
+        self.library_path.clone().or_else(|| {
+            std::env::var_os(LIBRARY_PATH_ENV)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+        })
+    }
+
+    /// Release tag to download, normalised to upstream's `v` prefixed tags.
+    pub(crate) fn version(&self) -> Option<String> {
+        self.version.clone()
+            .or_else(|| std::env::var(VERSION_ENV).ok())
+            .map(|version| version.trim().to_string())
+            .filter(|version| !version.is_empty())
+            .map(|version| match version.starts_with('v') {
+                true => version,
+                false => format!("v{}", version),
+            })
+    }
+
+    pub(crate) fn sha256(&self) -> Option<String> {
+        self.sha256.clone()
+    }
+
+    pub(crate) fn libc(&self) -> Option<Libc> {
+        match std::env::consts::OS {
+            "linux" => self.libc.or_else(Libc::detect),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn release_url(&self) -> String {
+        self.release_url.clone()
+            .or_else(|| std::env::var(RELEASE_URL_ENV).ok().filter(|url| !url.is_empty()))
+            .unwrap_or_else(|| DEFAULT_RELEASE_URL.to_string())
+    }
+
+    pub(crate) fn download_url(&self) -> Option<String> {
+        self.download_url.clone()
+    }
+
+    pub(crate) fn download_headers(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.headers.iter().map(|(key, value)| (key, value))
+    }
+
+    pub(crate) fn cache_dir(&self) -> Option<PathBuf> {
+        self.cache_dir.clone()
+    }
+
+    fn is_offline(&self) -> bool {
+        self.offline || std::env::var(OFFLINE_ENV)
+            .map(|value| !matches!(value.trim(), "" | "0" | "false"))
+            .unwrap_or(false)
+    }
+}
+
+
+/// Load the tls-client library using `options` and install it as the shared backend.
+///
+/// Calling this is optional, without it the library is loaded with the default options on the
+/// first request. Calling it again replaces the shared backend for subsequent requests.
+pub fn init(options: InitOptions) -> Result<Arc<TlsClientSharedMethods>, TlsClientError> {
+    let methods = Arc::new(load_library(&options)?);
+    set_shared_backend(methods.clone());
+    Ok(methods)
+}
+
+/// Outcome of replacing the shared backend with `swap_library` or `swap_shared_backend`.
+#[derive(Debug, Default, Clone)]
+pub struct SwapReport {
+    /// Whether every call running on the old backend finished within the drain timeout. When
+    /// `false` a slow request is still using it. Either way the old library is unloaded once the
+    /// last reference drops, which a `TlsClient` given it through `set_backend` may delay.
+    pub drained:        bool,
+    /// Sessions which only lived in the old library. Their cookies and connections are gone, so
+    /// callers should re-create them, e.g. by restoring saved cookies.
+    pub stale_sessions: Vec<String>,
+}
+
+/// Load a library using `options` and atomically switch new requests over to it.
+///
+/// Requests already running on the previous library are given up to `drain_timeout` to finish
+/// before it is unloaded. The new library must be a different file than the old one (e.g. another
+/// cached version), loading the same path again returns the already loaded library. Note that
+/// some platforms never unmap a Go runtime, in which case unloading only releases our handle.
+pub fn swap_library(options: InitOptions, drain_timeout: Duration) -> Result<SwapReport, TlsClientError> {
+    let methods = Arc::new(load_library(&options)?);
+    Ok(swap_shared_backend(methods, drain_timeout))
+}
+
+/// Atomically replace the shared backend with `backend`, draining the previous one as described
+/// in `swap_library`.
+pub fn swap_shared_backend(backend: Arc<dyn TlsBackend>, drain_timeout: Duration) -> SwapReport {
+    let previous = SHARED_BACKEND.write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .replace(Arc::new(TrackedBackend::new(backend)));
+    match previous {
+        Some(previous) => retire(&previous, drain_timeout),
+        None => SwapReport { drained: true, stale_sessions: vec![] },
+    }
+}
+
+/// Wait for the calls running on `backend` to finish, the last reference unloads it.
+fn retire(backend: &TrackedBackend, drain_timeout: Duration) -> SwapReport {
+    SwapReport {
+        drained: backend.wait_idle(drain_timeout),
+        stale_sessions: backend.active_sessions(),
+    }
+}
+
+/// Install `backend` as the transport for requests without an explicit backend.
+pub fn set_shared_backend(backend: Arc<dyn TlsBackend>) {
+    *SHARED_BACKEND.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(TrackedBackend::new(backend)));
+}
+
+/// The shared backend if it is loaded, without loading it. The lock is never held during a load.
+pub(crate) fn loaded_backend() -> Option<Arc<dyn TlsBackend>> {
+    SHARED_BACKEND.read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+        .map(|backend| backend as Arc<dyn TlsBackend>)
+}
+
+/// Destroy every session of the shared backend and release it, unloading the library once no
+/// request or `TlsClient::set_backend` holds on to it. A later request loads it again.
+pub fn shutdown() -> Result<(), TlsClientError> {
+    let backend = SHARED_BACKEND.write().unwrap_or_else(PoisonError::into_inner).take();
+    match backend {
+        Some(backend) if backend.library_info().is_none_or(|info| info.supports(Capability::DestroyAll)) => {
+            backend.destroy_all().map(|_| ())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Get the shared backend, loading the library with the default options if `init` was not called.
+///
+/// Load failures are returned rather than cached, so a later call can retry.
+pub fn shared_backend() -> Result<Arc<dyn TlsBackend>, TlsClientError> {
+    with_shared_backend(|backend| backend.clone() as Arc<dyn TlsBackend>)
+}
+
+/// Like `shared_backend`, but counting a call on it until the returned guard is dropped. A swap
+/// made while the caller is still preparing its call then waits for it as well.
+pub(crate) fn shared_call() -> Result<SharedCall, TlsClientError> {
+    with_shared_backend(|backend| {
+        backend.begin();
+        SharedCall(backend.clone())
+    })
+}
+
+/// Run `f` on the shared backend while holding the lock, loading the library first if needed.
+fn with_shared_backend<T>(f: impl FnOnce(&Arc<TrackedBackend>) -> T) -> Result<T, TlsClientError> {
+    if let Some(backend) = SHARED_BACKEND.read().unwrap_or_else(PoisonError::into_inner).as_ref() {
+        return Ok(f(backend));
+    }
+
+    // Load without holding the lock, so requests and dropped clients never wait on a download.
+    // Threads racing here may each load the library, only the first one installed is kept.
+    let loaded = Arc::new(TrackedBackend::new(Arc::new(load_library(&InitOptions::default())?)));
+
+    // Another thread may have installed a backend meanwhile
+    let mut guard = SHARED_BACKEND.write().unwrap_or_else(PoisonError::into_inner);
+    Ok(f(guard.get_or_insert(loaded)))
+}
+
+/// The shared backend, counting the calls running on it so a swap can wait for exactly those.
+#[derive(Debug)]
+struct TrackedBackend {
+    inner:     Arc<dyn TlsBackend>,
+    in_flight: Mutex<usize>,
+    idle:      Condvar,
+}
+
+impl TrackedBackend {
+    fn new(inner: Arc<dyn TlsBackend>) -> Self {
+        TrackedBackend { inner, in_flight: Mutex::new(0), idle: Condvar::new() }
+    }
+
+    fn track<T>(&self, call: impl FnOnce(&dyn TlsBackend) -> T) -> T {
+        self.begin();
+        let _call = InFlightCall(self);
+        call(self.inner.as_ref())
+    }
+
+    fn begin(&self) {
+        *self.in_flight.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+    }
+
+    fn end(&self) {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(PoisonError::into_inner);
+        *in_flight -= 1;
+        if *in_flight == 0 {
+            self.idle.notify_all();
+        }
+    }
+
+    /// Block until no call is running, returns `false` if one still is after `timeout`.
+    fn wait_idle(&self, timeout: Duration) -> bool {
+        let in_flight = self.in_flight.lock().unwrap_or_else(PoisonError::into_inner);
+        let (in_flight, _) = self.idle
+            .wait_timeout_while(in_flight, timeout, |in_flight| *in_flight > 0)
+            .unwrap_or_else(PoisonError::into_inner);
+        *in_flight == 0
+    }
+}
+
+/// Ends a tracked call when dropped, even if the call panicked.
+struct InFlightCall<'a>(&'a TrackedBackend);
+
+impl Drop for InFlightCall<'_> {
+    fn drop(&mut self) {
+        self.0.end();
+    }
+}
+
+/// The shared backend as returned by `shared_call`, keeping a swap from retiring it until dropped.
+pub(crate) struct SharedCall(Arc<TrackedBackend>);
+
+impl SharedCall {
+    pub(crate) fn backend(&self) -> Arc<dyn TlsBackend> {
+        self.0.clone()
+    }
+}
+
+impl Drop for SharedCall {
+    fn drop(&mut self) {
+        self.0.end();
+    }
+}
+
+impl TlsBackend for TrackedBackend {
+    fn request(&self, payload: &RequestPayload) -> Result<RequestResponse, TlsClientError> {
+        self.track(|backend| backend.request(payload))
+    }
+
+    fn get_cookies_from_session(&self, payload: String) -> Result<String, TlsClientError> {
+        self.track(|backend| backend.get_cookies_from_session(payload))
+    }
+
+    fn add_cookies_to_session(&self, payload: String) -> Result<String, TlsClientError> {
+        self.track(|backend| backend.add_cookies_to_session(payload))
+    }
+
+    fn destroy_session(&self, payload: String) -> Result<String, TlsClientError> {
+        self.track(|backend| backend.destroy_session(payload))
+    }
+
+    fn release_session(&self, payload: String) -> Result<String, TlsClientError> {
+        self.track(|backend| backend.release_session(payload))
+    }
+
+    fn destroy_all(&self) -> Result<String, TlsClientError> {
+        self.track(|backend| backend.destroy_all())
+    }
+
+    fn free_memory(&self, id: String) -> Result<(), TlsClientError> {
+        self.track(|backend| backend.free_memory(id))
+    }
+
+    fn library_info(&self) -> Option<LibraryInfo> {
+        self.inner.library_info()
+    }
+
+    fn active_sessions(&self) -> Vec<String> {
+        self.inner.active_sessions()
+    }
+
+    fn outstanding_allocations(&self) -> usize {
+        self.inner.outstanding_allocations()
+    }
+}
+
+fn load_library(options: &InitOptions) -> Result<TlsClientSharedMethods, TlsClientError> {
+    match resolve_library_path(options)? {
+        (path, Some(version)) => TlsClientSharedMethods::load(path, Some(version)),
+        (path, None) => TlsClientSharedMethods::new(path),
+    }
+}
+
+/// Path of the library to load, with its version when known from the cache.
+fn resolve_library_path(options: &InitOptions) -> Result<(PathBuf, Option<String>), TlsClientError> {
+    // An explicitly configured path must exist, falling back to a download would hide the mistake
+    if let Some(path) = options.library_path() {
+        return match path.is_file() {
+            true => Ok((path, None)),
+            false => Err(TlsClientError::LibraryNotFound(format!("`{}` does not exist", path.display()))),
+        };
+    }
+
+    if let Some(resolved) = vendored_library_path(options)? {
+        return Ok(resolved);
+    }
+
+    if let Some(bf) = TlsClientBinaryDownloader::cached(options) {
+        return Ok((PathBuf::from(bf.path()), Some(bf.version())));
+    }
+
+    if options.is_offline() {
+        return Err(TlsClientError::LibraryNotFound(format!(
+            "offline mode is enabled and no cached library was found, set `{}` or `InitOptions::with_library_path`",
+            LIBRARY_PATH_ENV,
+        )));
+    }
+
+    TlsClientBinaryDownloader::new(options).map(|bf| (PathBuf::from(bf.path()), Some(bf.version())))
+}
+
+/// Extract the library embedded by the `vendored` feature.
+#[cfg(feature = "vendored")]
+fn vendored_library_path(options: &InitOptions) -> Result<Option<(PathBuf, Option<String>)>, TlsClientError> {
+    crate::vendored::extract(options).map(|entry| entry.map(|entry| (entry.path, Some(entry.version))))
+}
+
+#[cfg(not(feature = "vendored"))]
+fn vendored_library_path(_options: &InitOptions) -> Result<Option<(PathBuf, Option<String>)>, TlsClientError> {
+    Ok(None)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::tests::FakeBackend;
+    use crate::client::TlsClient;
+    use super::*;
+
+    lazy_static! {
+        // Tests replacing the shared backend must not interleave
+        static ref GLOBAL_STATE: Mutex<()> = Mutex::new(());
+    }
+
+    #[test]
+    fn test_shared_backend_serves_clients_without_backend() {
+        let _global = GLOBAL_STATE.lock().unwrap_or_else(PoisonError::into_inner);
+        let backend = Arc::new(FakeBackend::default());
+        set_shared_backend(backend.clone());
+
+        let res = TlsClient::default().get("https://example.com").send();
+        *SHARED_BACKEND.write().unwrap_or_else(PoisonError::into_inner) = None;
+
+        assert_eq!(res.unwrap().get_status(), 200);
+        assert_eq!(backend.payloads.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_configured_path_is_used_without_download() {
+        let path = std::env::current_exe().unwrap();
+        let options = InitOptions::new().with_library_path(&path).with_offline(true);
+        assert_eq!(resolve_library_path(&options).unwrap().0, path);
+    }
+
+    #[test]
+    fn test_version_is_normalised() {
+        assert_eq!(InitOptions::new().with_version("1.7.2").version().unwrap(), "v1.7.2");
+        assert_eq!(InitOptions::new().with_version("v1.7.2").version().unwrap(), "v1.7.2");
+    }
+
+    #[test]
+    fn test_missing_configured_path_errors() {
+        let options = InitOptions::new().with_library_path("/does/not/exist/tls-client.so");
+        assert!(matches!(init(options), Err(TlsClientError::LibraryNotFound(_))));
+    }
+
+    #[test]
+    fn test_swap_waits_for_in_flight_requests() {
+        let _global = GLOBAL_STATE.lock().unwrap_or_else(PoisonError::into_inner);
+        let old = Arc::new(FakeBackend {
+            delay: Some(Duration::from_millis(100)),
+            ..Default::default()
+        });
+        set_shared_backend(old.clone());
+
+        let in_flight = std::thread::spawn(|| {
+            TlsClient::default().into_detached().get("https://example.com").send().unwrap()
+        });
+        // Give the request time to start
+        std::thread::sleep(Duration::from_millis(20));
+
+        let new = Arc::new(FakeBackend::default());
+        let report = swap_shared_backend(new.clone(), Duration::from_secs(5));
+        let res = in_flight.join().unwrap();
+        *SHARED_BACKEND.write().unwrap_or_else(PoisonError::into_inner) = None;
+
+        assert!(report.drained);
+        assert!(report.stale_sessions.contains(&res.session_id));
+        assert_eq!(old.payloads.lock().unwrap().len(), 1);
+        assert!(new.payloads.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_swap_waits_for_calls_being_prepared() {
+        let _global = GLOBAL_STATE.lock().unwrap_or_else(PoisonError::into_inner);
+        let old = Arc::new(FakeBackend::default());
+        set_shared_backend(old.clone());
+
+        let call = shared_call().unwrap();
+        let swap = std::thread::spawn(|| swap_shared_backend(Arc::new(FakeBackend::default()), Duration::from_secs(5)));
+        // Let the swap replace the backend before the call is made
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!swap.is_finished());
+
+        let res = call.backend().request(&TlsClient::default().into_detached().get("https://example.com")).unwrap();
+        drop(call);
+        let report = swap.join().unwrap();
+        *SHARED_BACKEND.write().unwrap_or_else(PoisonError::into_inner) = None;
+
+        assert!(report.drained);
+        assert!(report.stale_sessions.contains(&res.session_id));
+        assert_eq!(old.payloads.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_swap_reports_undrained_backend() {
+        let _global = GLOBAL_STATE.lock().unwrap_or_else(PoisonError::into_inner);
+        set_shared_backend(Arc::new(FakeBackend {
+            delay: Some(Duration::from_millis(200)),
+            ..Default::default()
+        }));
+
+        let in_flight = std::thread::spawn(|| {
+            TlsClient::default().into_detached().get("https://example.com").send().unwrap()
+        });
+        std::thread::sleep(Duration::from_millis(20));
+
+        let report = swap_shared_backend(Arc::new(FakeBackend::default()), Duration::from_millis(20));
+        in_flight.join().unwrap();
+        *SHARED_BACKEND.write().unwrap_or_else(PoisonError::into_inner) = None;
+
+        assert!(!report.drained);
+    }
+
+    #[test]
+    fn test_idle_references_do_not_block_swap() {
+        let _global = GLOBAL_STATE.lock().unwrap_or_else(PoisonError::into_inner);
+        set_shared_backend(Arc::new(FakeBackend::default()));
+        let _client = TlsClient::default().set_backend(loaded_backend().unwrap());
+
+        let report = swap_shared_backend(Arc::new(FakeBackend::default()), Duration::from_millis(20));
+        *SHARED_BACKEND.write().unwrap_or_else(PoisonError::into_inner) = None;
+
+        assert!(report.drained);
+        assert!(report.stale_sessions.is_empty());
+    }
+}
@@ -1,16 +1,19 @@
-use rust_tls_client::{TlsClient, ClientIdentifier};
+use std::io::{stdin, stdout};
+use rust_tls_client::{serve_helper, shared_backend};
 
+/// Helper process for `ProcessBackend`: loads the tls-client library (configured by the usual
+/// `RUST_TLS_CLIENT_*` environment variables) and serves calls as JSON lines on stdin/stdout.
 fn main() {
-    let client = TlsClient::new(
-        ClientIdentifier::Chrome110, true
-    );
-    let res = client.get("https://www.google.com")
-        .send()
-        .unwrap();
-    println!("{}", serde_json::to_string(&res).unwrap());
+    let backend = match shared_backend() {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("rust_tls_client: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let res2 = client.get("https://www.google.com")
-        .send()
-        .unwrap();
-    println!("{}", serde_json::to_string(&res2).unwrap());
-}
\ No newline at end of file
+    if let Err(e) = serve_helper(backend.as_ref(), stdin().lock(), stdout()) {
+        eprintln!("rust_tls_client: {}", e);
+        std::process::exit(1);
+    }
+}
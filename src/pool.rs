@@ -0,0 +1,144 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::JoinHandle;
+use crate::error::TlsClientError;
+use crate::request::RequestPayload;
+use crate::response::RequestResponse;
+
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+
+/// A fixed number of threads running blocking library calls.
+///
+/// The exports of the library are safe to call concurrently (see `TlsClientSharedMethods`), so
+/// the pool only bounds how many requests are in flight, e.g. to stay within a proxy's connection
+/// limit. Jobs beyond that wait in a queue. Dropping the pool finishes the queued jobs and joins
+/// its threads.
+#[derive(Debug)]
+pub struct WorkerPool {
+    sender:     Option<Sender<Job>>,
+    workers:    Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// Pool running at most `parallelism` calls at once (at least one).
+    pub fn new(parallelism: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..parallelism.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("rust-tls-client-{}", i))
+                    .spawn(move || loop {
+                        // Only hold the lock while waiting, so the other workers can pick up jobs
+                        let job = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+                        match job {
+                            // A panicking job drops its result sender, which is reported to the caller
+                            Ok(job) => { let _ = std::panic::catch_unwind(AssertUnwindSafe(job)); }
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+
+        Self { sender: Some(sender), workers }
+    }
+
+    /// Number of threads in the pool.
+    pub fn parallelism(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Queue `job`, returning the channel its result is sent on. The channel disconnects without a
+    /// result if the job panics.
+    pub(crate) fn execute<T, F>(&self, job: F) -> Receiver<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (result_sender, result_receiver) = mpsc::channel();
+        self.spawn(move || {
+            let _ = result_sender.send(job());
+        });
+        result_receiver
+    }
+
+    /// Queue `job` without waiting for it, a panic only ends the job.
+    pub(crate) fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Box::new(job));
+        }
+    }
+
+    /// Send every payload, at most `parallelism` at a time, returning the results in input order.
+    pub fn send_all(&self, payloads: Vec<RequestPayload>) -> Vec<Result<RequestResponse, TlsClientError>> {
+        let pending: Vec<_> = payloads.into_iter()
+            .map(|payload| self.execute(move || payload.send()))
+            .collect();
+
+        pending.into_iter()
+            .map(|receiver| receiver.recv().unwrap_or_else(|_| Err(TlsClientError::GeneralError(
+                String::from("Request panicked on a worker thread"),
+            ))))
+            .collect()
+    }
+}
+
+impl Default for WorkerPool {
+    /// Pool with one thread per available CPU.
+    fn default() -> Self {
+        Self::new(std::thread::available_parallelism().map(usize::from).unwrap_or(4))
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // Closing the queue stops each worker once it is drained
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::backend::tests::{fake_client, FakeBackend};
+    use super::*;
+
+    #[test]
+    fn test_send_all_keeps_input_order() {
+        let (backend, client) = fake_client(FakeBackend {
+            delay: Some(Duration::from_millis(50)),
+            ..Default::default()
+        });
+        let urls: Vec<String> = (0..8).map(|i| format!("https://example.com/{}", i)).collect();
+
+        let pool = WorkerPool::new(4);
+        let started = Instant::now();
+        let results = pool.send_all(urls.iter().map(|url| client.get(url)).collect());
+
+        // Eight requests on four threads take at least two round trips
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        let targets: Vec<String> = results.into_iter().map(|res| res.unwrap().target).collect();
+        assert_eq!(targets, urls);
+        assert_eq!(backend.payloads.lock().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn test_panicking_job_does_not_kill_worker() {
+        let pool = WorkerPool::new(1);
+        assert!(pool.execute(|| panic!("job failed")).recv().is_err());
+        assert_eq!(pool.execute(|| 7).recv().unwrap(), 7);
+    }
+}
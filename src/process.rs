@@ -0,0 +1,612 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::backend::{serialize_request, DestroySessionPayload, TlsBackend};
+use crate::capability::{Capability, LibraryInfo};
+use crate::error::TlsClientError;
+use crate::request::RequestPayload;
+use crate::response::RequestResponse;
+
+
+const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Longest a dropped client's session teardown may wait for the helper
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+/// Calls understood by `serve_helper`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum HelperMethod {
+    Request,
+    GetCookiesFromSession,
+    AddCookiesToSession,
+    DestroySession,
+    DestroyAll,
+    LibraryInfo,
+}
+
+impl HelperMethod {
+    fn name(&self) -> &'static str {
+        match self {
+            HelperMethod::Request => "request",
+            HelperMethod::GetCookiesFromSession => "getCookiesFromSession",
+            HelperMethod::AddCookiesToSession => "addCookiesToSession",
+            HelperMethod::DestroySession => "destroySession",
+            HelperMethod::DestroyAll => "destroyAll",
+            HelperMethod::LibraryInfo => "libraryInfo",
+        }
+    }
+}
+
+/// One line written to the helper's stdin.
+#[derive(Debug, Serialize, Deserialize)]
+struct HelperCall {
+    id:         u64,
+    method:     HelperMethod,
+    #[serde(default)]
+    payload:    String,
+}
+
+/// One line written to the helper's stdout, answering the call with the same `id`.
+#[derive(Debug, Serialize, Deserialize)]
+struct HelperReply {
+    id:         u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result:     Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error:      Option<TlsClientError>,
+}
+
+
+/// Serve calls from a `ProcessBackend` with `backend`, reading JSON lines from `input` and writing
+/// one JSON line per call to `output`, until `input` is closed.
+///
+/// Calls run concurrently and are answered as they complete. The crate's `rust_tls_client` binary
+/// runs this with the shared library, but it can be embedded into any binary whose stdout is
+/// otherwise unused (lines which are not replies, e.g. debug output of the library, are ignored).
+pub fn serve_helper<R: BufRead, W: Write + Send>(backend: &dyn TlsBackend, input: R, output: W) -> std::io::Result<()> {
+    let output = Mutex::new(output);
+    std::thread::scope(|scope| {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let output = &output;
+            scope.spawn(move || {
+                let reply = match serde_json::from_str::<HelperCall>(&line) {
+                    Ok(call) => dispatch(backend, call),
+                    // Ids start at 1, so this never resolves a pending call
+                    Err(e) => HelperReply {
+                        id: 0,
+                        result: None,
+                        error: Some(TlsClientError::GeneralError(format!("Invalid helper call: {}", e))),
+                    },
+                };
+
+                // The parent going away is noticed by the read loop, so write errors are ignored
+                let mut output = output.lock().unwrap_or_else(PoisonError::into_inner);
+                if let Ok(line) = serde_json::to_string(&reply) {
+                    let _ = writeln!(output, "{}", line).and_then(|_| output.flush());
+                }
+            });
+        }
+        Ok(())
+    })
+}
+
+fn dispatch(backend: &dyn TlsBackend, call: HelperCall) -> HelperReply {
+    let serialize_error = |e: serde_json::Error| TlsClientError::GeneralError(format!("Failed to serialize reply: {}", e));
+    let result = match call.method {
+        HelperMethod::Request => serde_json::from_str::<RequestPayload>(&call.payload)
+            .map_err(|e| TlsClientError::GeneralError(format!("Invalid request payload: {}", e)))
+            .and_then(|payload| backend.request(&payload))
+            .and_then(|response| serde_json::to_string(&response).map_err(serialize_error)),
+        HelperMethod::GetCookiesFromSession => backend.get_cookies_from_session(call.payload),
+        HelperMethod::AddCookiesToSession => backend.add_cookies_to_session(call.payload),
+        HelperMethod::DestroySession => backend.destroy_session(call.payload),
+        HelperMethod::DestroyAll => backend.destroy_all(),
+        HelperMethod::LibraryInfo => serde_json::to_string(&backend.library_info()).map_err(serialize_error),
+    };
+
+    match result {
+        Ok(result) => HelperReply { id: call.id, result: Some(result), error: None },
+        Err(error) => HelperReply { id: call.id, result: None, error: Some(error) },
+    }
+}
+
+
+// Reply channels of the calls a helper has not answered yet, `None` once its stdout closed
+type PendingCalls = Arc<Mutex<Option<HashMap<u64, Sender<HelperReply>>>>>;
+
+// Cookies seen per session, by url and cookie name, replayed into a restarted helper
+type SessionCookies = BTreeMap<String, BTreeMap<String, Value>>;
+
+
+/// Backend running the tls-client library in a child process, so a crash or hang inside the Go
+/// runtime cannot take down this process.
+///
+/// The helper (e.g. the crate's `rust_tls_client` binary, see `serve_helper`) is started on
+/// the first call and restarted on the next call after it exits. Every call has a deadline, after
+/// which it fails with `TlsClientError::Timeout` and the helper is presumed wedged and killed.
+/// Calls in flight when the helper dies fail with `TlsClientError::Helper`, they are not retried.
+///
+/// Sessions only live inside the helper, so cookies received by or added to each session are
+/// replayed into a restarted helper when it supports `addCookiesToSession`. Other session state,
+/// such as open connections, is lost.
+#[derive(Debug)]
+pub struct ProcessBackend {
+    program:        PathBuf,
+    args:           Vec<OsString>,
+    envs:           Vec<(OsString, OsString)>,
+    call_timeout:   Duration,
+    helper:         Mutex<Option<Helper>>,
+    info:           Mutex<Option<LibraryInfo>>,
+    sessions:       Mutex<HashMap<String, SessionCookies>>,
+    next_id:        AtomicU64,
+    spawned:        AtomicUsize,
+}
+
+impl ProcessBackend {
+    /// Backend running `program` as the helper. The helper inherits this process' environment, so
+    /// the `RUST_TLS_CLIENT_*` variables configure which library it loads.
+    pub fn new<P: Into<PathBuf>>(program: P) -> Self {
+        Self {
+            program: program.into(),
+            args: vec![],
+            envs: vec![],
+            call_timeout: DEFAULT_CALL_TIMEOUT,
+            helper: Mutex::new(None),
+            info: Mutex::new(None),
+            sessions: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            spawned: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn with_arg<S: Into<OsString>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn with_env<K: Into<OsString>, V: Into<OsString>>(mut self, key: K, value: V) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// Deadline of each call, including the network round trip of requests. Defaults to 120 seconds.
+    pub fn with_call_timeout(mut self, timeout: Duration) -> Self {
+        self.call_timeout = timeout;
+        self
+    }
+
+    /// Number of times the helper was started again after exiting or timing out.
+    pub fn restarts(&self) -> usize {
+        self.spawned.load(Ordering::SeqCst).saturating_sub(1)
+    }
+
+    /// Lock the helper slot, starting a new helper if there is none or the last one exited.
+    fn running(&self) -> Result<MutexGuard<'_, Option<Helper>>, TlsClientError> {
+        let mut slot = lock(&self.helper);
+        if !slot.as_ref().is_some_and(Helper::is_alive) {
+            // Drop (and reap) the dead helper before starting its replacement
+            slot.take();
+            *slot = Some(self.spawn()?);
+        }
+        Ok(slot)
+    }
+
+    fn spawn(&self) -> Result<Helper, TlsClientError> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .envs(self.envs.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| TlsClientError::Helper(format!("Failed to start `{}`: {}", self.program.display(), e)))?;
+
+        let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            _ => return Err(TlsClientError::Helper(String::from("Helper stdio is not piped"))),
+        };
+
+        let pending: PendingCalls = Arc::new(Mutex::new(Some(HashMap::new())));
+        let reader_pending = pending.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                let Ok(reply) = serde_json::from_str::<HelperReply>(&line) else { continue };
+                let sender = lock(&reader_pending).as_mut().and_then(|pending| pending.remove(&reply.id));
+                if let Some(sender) = sender {
+                    let _ = sender.send(reply);
+                }
+            }
+            // Dropping the senders fails every call still waiting on this helper
+            lock(&reader_pending).take();
+        });
+
+        let generation = self.spawned.fetch_add(1, Ordering::SeqCst);
+        let mut helper = Helper { generation, child, stdin, pending };
+
+        let info: Option<LibraryInfo> = self.call_on(&mut helper, HelperMethod::LibraryInfo, String::new())
+            .and_then(|info| serde_json::from_str(&info)
+                .map_err(|e| TlsClientError::Helper(format!("Invalid library info: {}", e))))?;
+        if info.as_ref().is_none_or(|info| info.supports(Capability::AddCookiesToSession)) {
+            self.restore_sessions(&mut helper);
+        }
+        *lock(&self.info) = info;
+
+        Ok(helper)
+    }
+
+    /// Replay the cookies of every known session, best effort.
+    fn restore_sessions(&self, helper: &mut Helper) {
+        let sessions = lock(&self.sessions).clone();
+        for (session_id, urls) in sessions {
+            for (url, cookies) in urls.into_iter().filter(|(_, cookies)| !cookies.is_empty()) {
+                let payload = json!({
+                    "sessionId": session_id,
+                    "url": url,
+                    "cookies": cookies.into_values().collect::<Vec<_>>(),
+                });
+                let _ = self.call_on(helper, HelperMethod::AddCookiesToSession, payload.to_string());
+            }
+        }
+    }
+
+    /// Call `helper` while holding its slot, used while it is being started.
+    fn call_on(&self, helper: &mut Helper, method: HelperMethod, payload: String) -> Result<String, TlsClientError> {
+        let receiver = helper.send(self.next_id.fetch_add(1, Ordering::SeqCst), method, payload)?;
+        self.wait(receiver, method, self.call_timeout)
+    }
+
+    fn call(&self, method: HelperMethod, payload: String) -> Result<String, TlsClientError> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (generation, receiver) = {
+            let mut slot = self.running()?;
+            let helper = slot.as_mut().ok_or_else(|| TlsClientError::Helper(String::from("Helper is not running")))?;
+            (helper.generation, helper.send(id, method, payload)?)
+        };
+
+        let res = self.wait(receiver, method, self.call_timeout);
+        if let Err(TlsClientError::Timeout(_)) = res {
+            // Kill the wedged helper, unless it was already replaced
+            let mut slot = lock(&self.helper);
+            if slot.as_ref().is_some_and(|helper| helper.generation == generation) {
+                slot.take();
+            }
+        }
+        res
+    }
+
+    fn wait(&self, receiver: Receiver<HelperReply>, method: HelperMethod, timeout: Duration) -> Result<String, TlsClientError> {
+        match receiver.recv_timeout(timeout) {
+            Ok(HelperReply { error: Some(error), .. }) => Err(error),
+            Ok(HelperReply { result, .. }) => Ok(result.unwrap_or_default()),
+            Err(RecvTimeoutError::Timeout) => Err(TlsClientError::Timeout(timeout)),
+            Err(RecvTimeoutError::Disconnected) => Err(TlsClientError::Helper(format!(
+                "Helper exited during `{}`", method.name(),
+            ))),
+        }
+    }
+
+    fn record_response(&self, payload: &RequestPayload, response: &RequestResponse) {
+        let session_id = match payload.session_id() {
+            Some(session_id) => session_id,
+            None => return,
+        };
+
+        let mut sessions = lock(&self.sessions);
+        let session = sessions.entry(session_id.to_string()).or_default();
+        if let (Some(url), Some(cookies)) = (payload.url(), &response.cookies) {
+            let known = session.entry(url.to_string()).or_default();
+            for (name, value) in cookies {
+                known.insert(name.clone(), json!({ "name": name, "value": value }));
+            }
+        }
+    }
+
+    fn record_added_cookies(&self, payload: &str) {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AddCookiesPayload {
+            session_id: String,
+            url:        String,
+            cookies:    Vec<Value>,
+        }
+
+        if let Ok(added) = serde_json::from_str::<AddCookiesPayload>(payload) {
+            let mut sessions = lock(&self.sessions);
+            let known = sessions.entry(added.session_id).or_default().entry(added.url).or_default();
+            for cookie in added.cookies {
+                if let Some(name) = cookie.get("name").and_then(Value::as_str) {
+                    known.insert(name.to_string(), cookie.clone());
+                }
+            }
+        }
+    }
+}
+
+impl TlsBackend for ProcessBackend {
+    fn request(&self, payload: &RequestPayload) -> Result<RequestResponse, TlsClientError> {
+        let serialized = serialize_request(payload, |reason| TlsClientError::Helper(format!("Invalid `request` call: {}", reason)))?;
+        let response: RequestResponse = serde_json::from_str(&self.call(HelperMethod::Request, serialized)?)
+            .map_err(|e| TlsClientError::Helper(format!("Invalid `request` reply: {}", e)))?;
+        self.record_response(payload, &response);
+        Ok(response)
+    }
+
+    fn get_cookies_from_session(&self, payload: String) -> Result<String, TlsClientError> {
+        self.call(HelperMethod::GetCookiesFromSession, payload)
+    }
+
+    fn add_cookies_to_session(&self, payload: String) -> Result<String, TlsClientError> {
+        let res = self.call(HelperMethod::AddCookiesToSession, payload.clone())?;
+        self.record_added_cookies(&payload);
+        Ok(res)
+    }
+
+    fn destroy_session(&self, payload: String) -> Result<String, TlsClientError> {
+        let res = self.call(HelperMethod::DestroySession, payload.clone())?;
+        if let Some(session_id) = DestroySessionPayload::session_id(&payload) {
+            lock(&self.sessions).remove(&session_id);
+        }
+        Ok(res)
+    }
+
+    /// Destroy the session only if the helper is running, without restarting it or killing it
+    /// when the call times out.
+    fn release_session(&self, payload: String) -> Result<String, TlsClientError> {
+        if let Some(session_id) = DestroySessionPayload::session_id(&payload) {
+            // Not replayed into a later helper either way
+            lock(&self.sessions).remove(&session_id);
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let receiver = match lock(&self.helper).as_mut().filter(|helper| helper.is_alive()) {
+            Some(helper) => helper.send(id, HelperMethod::DestroySession, payload)?,
+            None => return Ok(String::new()),
+        };
+        self.wait(receiver, HelperMethod::DestroySession, self.call_timeout.min(RELEASE_TIMEOUT))
+    }
+
+    fn destroy_all(&self) -> Result<String, TlsClientError> {
+        let res = self.call(HelperMethod::DestroyAll, String::new())?;
+        lock(&self.sessions).clear();
+        Ok(res)
+    }
+
+    /// Responses are freed inside the helper, so there is nothing to free here.
+    fn free_memory(&self, _id: String) -> Result<(), TlsClientError> {
+        Ok(())
+    }
+
+    /// Info of the library loaded by the helper, starting it if needed. `None` if it cannot be started.
+    fn library_info(&self) -> Option<LibraryInfo> {
+        if let Some(info) = lock(&self.info).clone() {
+            return Some(info);
+        }
+        drop(self.running().ok()?);
+        lock(&self.info).clone()
+    }
+
+    fn active_sessions(&self) -> Vec<String> {
+        let mut sessions: Vec<String> = lock(&self.sessions).keys().cloned().collect();
+        sessions.sort();
+        sessions
+    }
+}
+
+
+/// A running helper process, killed when dropped.
+#[derive(Debug)]
+struct Helper {
+    generation: usize,
+    child:      Child,
+    stdin:      ChildStdin,
+    pending:    PendingCalls,
+}
+
+impl Helper {
+    fn is_alive(&self) -> bool {
+        lock(&self.pending).is_some()
+    }
+
+    /// Write a call, returning the channel its reply arrives on.
+    fn send(&mut self, id: u64, method: HelperMethod, payload: String) -> Result<Receiver<HelperReply>, TlsClientError> {
+        let line = serde_json::to_string(&HelperCall { id, method, payload })
+            .map_err(|e| TlsClientError::Helper(format!("Failed to serialize `{}` call: {}", method.name(), e)))?;
+
+        let (sender, receiver) = mpsc::channel();
+        match lock(&self.pending).as_mut() {
+            Some(pending) => pending.insert(id, sender),
+            None => return Err(TlsClientError::Helper(format!("Helper exited before `{}`", method.name()))),
+        };
+
+        writeln!(self.stdin, "{}", line)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| TlsClientError::Helper(format!("Failed to send `{}` to the helper: {}", method.name(), e)))?;
+        Ok(receiver)
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::tests::FakeBackend;
+    use crate::client::TlsClient;
+    use crate::client::tests::wait_for_teardown;
+    use super::*;
+
+    // Answers each call with the reply stored in `<dir>/<method>`, records calls in `<dir>/log`,
+    // exits on a request while `<dir>/crash` exists and hangs on one while `<dir>/hang` exists
+    const FAKE_HELPER: &str = r#"
+        while IFS= read -r line; do
+            printf '%s\n' "$line" >> "$0/log"
+            id=${line#*\"id\":}; id=${id%%,*}
+            method=${line#*\"method\":\"}; method=${method%%\"*}
+            if [ "$method" = request ] && [ -f "$0/crash" ]; then rm "$0/crash"; exit 1; fi
+            if [ "$method" = request ] && [ -f "$0/hang" ]; then rm "$0/hang"; read -r _; fi
+            printf '{"id":%s,%s}\n' "$id" "$(cat "$0/$method")"
+        done
+    "#;
+
+    fn fake_helper(timeout: Duration) -> (ProcessBackend, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("rust_tls_client_helper_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let info = LibraryInfo::new(Some(String::from("v1.7.2")), Capability::REQUIRED.iter()
+            .chain(&[Capability::AddCookiesToSession])
+            .copied()
+            .collect());
+        let response = RequestResponse {
+            id: String::from("response"),
+            status: 200,
+            cookies: Some(HashMap::from([(String::from("token"), String::from("abc"))])),
+            ..Default::default()
+        };
+        let reply = |result: String| serde_json::to_string(&json!({ "result": result })).unwrap();
+        let reply = |result: String| reply(result).trim_start_matches('{').trim_end_matches('}').to_string();
+        std::fs::write(dir.join("libraryInfo"), reply(serde_json::to_string(&Some(info)).unwrap())).unwrap();
+        std::fs::write(dir.join("request"), reply(serde_json::to_string(&response).unwrap())).unwrap();
+        std::fs::write(dir.join("addCookiesToSession"), reply(String::from("{}"))).unwrap();
+        std::fs::write(dir.join("destroySession"), reply(String::from("{}"))).unwrap();
+
+        let backend = ProcessBackend::new("sh")
+            .with_arg("-c")
+            .with_arg(FAKE_HELPER)
+            .with_arg(&dir)
+            .with_call_timeout(timeout);
+        (backend, dir)
+    }
+
+    fn logged_methods(dir: &std::path::Path) -> Vec<String> {
+        std::fs::read_to_string(dir.join("log")).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<HelperCall>(line).unwrap().method.name().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_serve_helper() {
+        let backend = FakeBackend::default();
+        let payload = serde_json::to_string(&TlsClient::default().get("https://example.com")).unwrap();
+        let input = [
+            serde_json::to_string(&HelperCall { id: 1, method: HelperMethod::Request, payload }).unwrap(),
+            serde_json::to_string(&HelperCall { id: 2, method: HelperMethod::DestroyAll, payload: String::new() }).unwrap(),
+            String::from("not json"),
+        ].join("\n");
+
+        let mut output = Vec::new();
+        serve_helper(&backend, input.as_bytes(), &mut output).unwrap();
+
+        let mut replies: Vec<HelperReply> = String::from_utf8(output).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        replies.sort_by_key(|reply| reply.id);
+
+        assert_eq!(replies.len(), 3);
+        assert!(matches!(replies[0].error, Some(TlsClientError::GeneralError(_))));
+        let response: RequestResponse = serde_json::from_str(replies[1].result.as_ref().unwrap()).unwrap();
+        assert_eq!(response.target, "https://example.com/");
+        assert_eq!(replies[2].result.as_deref(), Some(""));
+        assert_eq!(backend.payloads.lock().unwrap().len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restart_restores_session_cookies() {
+        let (backend, dir) = fake_helper(Duration::from_secs(10));
+        let backend = Arc::new(backend);
+        let client = TlsClient::default().set_backend(backend.clone());
+
+        assert!(client.get("https://example.com").send().is_ok());
+        assert_eq!(backend.library_info().unwrap().version(), Some("v1.7.2"));
+
+        std::fs::write(dir.join("crash"), "").unwrap();
+        assert!(matches!(client.get("https://example.com").send(), Err(TlsClientError::Helper(_))));
+
+        assert!(client.get("https://example.com").send().is_ok());
+        assert_eq!(backend.restarts(), 1);
+        assert_eq!(logged_methods(&dir), vec![
+            "libraryInfo", "request", "request",
+            "libraryInfo", "addCookiesToSession", "request",
+        ]);
+
+        let log = std::fs::read_to_string(dir.join("log")).unwrap();
+        let restored: HelperCall = serde_json::from_str(log.lines().nth(4).unwrap()).unwrap();
+        let restored: Value = serde_json::from_str(&restored.payload).unwrap();
+        assert_eq!(restored["url"], "https://example.com/");
+        assert_eq!(restored["cookies"], json!([{ "name": "token", "value": "abc" }]));
+
+        // Destroys the session through the helper, which needs the reply files
+        drop(client);
+        wait_for_teardown();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_call_deadline_restarts_helper() {
+        let (backend, dir) = fake_helper(Duration::from_millis(500));
+        let backend = Arc::new(backend);
+        let client = TlsClient::default().set_backend(backend.clone());
+
+        std::fs::write(dir.join("hang"), "").unwrap();
+        assert!(matches!(client.get("https://example.com").send(), Err(TlsClientError::Timeout(_))));
+
+        assert!(client.get("https://example.com").send().is_ok());
+        assert_eq!(backend.restarts(), 1);
+
+        // Destroys the session through the helper, which needs the reply files
+        drop(client);
+        wait_for_teardown();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_drop_does_not_start_helper() {
+        let (backend, dir) = fake_helper(Duration::from_secs(10));
+        let backend = Arc::new(backend);
+
+        drop(TlsClient::default().set_backend(backend.clone()));
+        wait_for_teardown();
+        assert!(!dir.join("log").exists());
+
+        // A running helper is asked to destroy the session
+        let client = TlsClient::default().set_backend(backend.clone());
+        assert!(client.get("https://example.com").send().is_ok());
+        drop(client);
+        wait_for_teardown();
+        assert_eq!(logged_methods(&dir), vec!["libraryInfo", "request", "destroySession"]);
+        assert!(backend.active_sessions().is_empty());
+        assert_eq!(backend.restarts(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
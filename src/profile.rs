@@ -0,0 +1,249 @@
+use crate::types::ClientIdentifier;
+
+
+/// What kind of request a browser makes, which decides its `Accept` and `sec-fetch-*` headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RequestContext {
+    /// A top-level page load, e.g. typing the url or following a link.
+    #[default]
+    Navigation,
+    /// A same-origin `XMLHttpRequest` made by a page.
+    Xhr,
+    /// A same-origin `fetch()` made by a page. Browsers currently send the same headers as for
+    /// `Xhr`, both exist so call sites can say which one they emulate.
+    Fetch,
+}
+
+
+/// The browser a `ClientIdentifier` impersonates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Browser {
+    Chrome(u16),
+    Opera { version: u16, chromium: u16 },
+    Firefox(u16),
+    Safari(&'static str),
+    SafariMobile { version: &'static str, device: &'static str },
+}
+
+impl ClientIdentifier {
+    fn browser(&self) -> Option<Browser> {
+        match self {
+            ClientIdentifier::Chrome103 => Some(Browser::Chrome(103)),
+            ClientIdentifier::Chrome104 => Some(Browser::Chrome(104)),
+            ClientIdentifier::Chrome105 => Some(Browser::Chrome(105)),
+            ClientIdentifier::Chrome106 => Some(Browser::Chrome(106)),
+            ClientIdentifier::Chrome107 => Some(Browser::Chrome(107)),
+            ClientIdentifier::Chrome108 => Some(Browser::Chrome(108)),
+            ClientIdentifier::Chrome109 => Some(Browser::Chrome(109)),
+            ClientIdentifier::Chrome110 => Some(Browser::Chrome(110)),
+            ClientIdentifier::Chrome111 => Some(Browser::Chrome(111)),
+            ClientIdentifier::Chrome112 => Some(Browser::Chrome(112)),
+            ClientIdentifier::Chrome116Psk | ClientIdentifier::Chrome116PskPq => Some(Browser::Chrome(116)),
+            ClientIdentifier::Chrome117 => Some(Browser::Chrome(117)),
+            ClientIdentifier::Chrome120 => Some(Browser::Chrome(120)),
+            ClientIdentifier::Safari1561 => Some(Browser::Safari("15.6.1")),
+            ClientIdentifier::Safari160 => Some(Browser::Safari("16.0")),
+            ClientIdentifier::SafariIOS155 => Some(Browser::SafariMobile { version: "15.5", device: "iPhone; CPU iPhone" }),
+            ClientIdentifier::SafariIOS156 => Some(Browser::SafariMobile { version: "15.6", device: "iPhone; CPU iPhone" }),
+            ClientIdentifier::SafariIOS160 => Some(Browser::SafariMobile { version: "16.0", device: "iPhone; CPU iPhone" }),
+            ClientIdentifier::SafariIPadOS156 => Some(Browser::SafariMobile { version: "15.6", device: "iPad; CPU" }),
+            ClientIdentifier::Firefox102 => Some(Browser::Firefox(102)),
+            ClientIdentifier::Firefox104 => Some(Browser::Firefox(104)),
+            ClientIdentifier::Firefox105 => Some(Browser::Firefox(105)),
+            ClientIdentifier::Firefox106 => Some(Browser::Firefox(106)),
+            ClientIdentifier::Firefox108 => Some(Browser::Firefox(108)),
+            ClientIdentifier::Firefox110 => Some(Browser::Firefox(110)),
+            ClientIdentifier::Firefox117 => Some(Browser::Firefox(117)),
+            ClientIdentifier::Opera89 => Some(Browser::Opera { version: 89, chromium: 103 }),
+            ClientIdentifier::Opera90 => Some(Browser::Opera { version: 90, chromium: 104 }),
+            ClientIdentifier::Opera91 => Some(Browser::Opera { version: 91, chromium: 105 }),
+            // App profiles send whatever their app sends, there is no browser to copy
+            _ => None,
+        }
+    }
+
+    /// Headers the impersonated browser sends for a request in `context`, in the browser's order
+    /// with lower-case names. `None` for the app profiles, which have no browser to copy.
+    pub fn default_headers(&self, context: RequestContext) -> Option<Vec<(&'static str, String)>> {
+        Some(match self.browser()? {
+            Browser::Chrome(version) => chromium_headers(
+                context,
+                chrome_brands(version, "Google Chrome", version),
+                format!("Chrome/{}.0.0.0 Safari/537.36", version),
+                version,
+            ),
+            Browser::Opera { version, chromium } => chromium_headers(
+                context,
+                chrome_brands(chromium, "Opera", version),
+                format!("Chrome/{}.0.0.0 Safari/537.36 OPR/{}.0.0.0", chromium, version),
+                chromium,
+            ),
+            Browser::Firefox(version) => firefox_headers(context, version),
+            Browser::Safari(version) => safari_headers(context, format!(
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/{} Safari/605.1.15",
+                version,
+            )),
+            Browser::SafariMobile { version, device } => safari_headers(context, format!(
+                "Mozilla/5.0 ({} OS {} like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/{} Mobile/15E148 Safari/604.1",
+                device, version.replace('.', "_"), version,
+            )),
+        })
+    }
+}
+
+
+const ACCEPT_ANY: &str = "*/*";
+const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+/// `sec-ch-ua` of Chromium `version`, with the GREASE brand and brand order Chromium derives
+/// from its major version (`GetUserAgentBrandList`).
+fn chrome_brands(version: u16, brand: &str, brand_version: u16) -> String {
+    const ESCAPED_CHARS: [char; 11] = [' ', '(', ':', '-', '.', '/', ')', ';', '=', '?', '_'];
+    const GREASE_VERSIONS: [&str; 3] = ["8", "99", "24"];
+    const ORDERS: [[usize; 3]; 6] = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+
+    let seed = usize::from(version);
+    let (grease, grease_version) = match version {
+        // Before 105 the GREASE brand was picked from a fixed list
+        ..=103 => (String::from(".Not/A)Brand"), "99"),
+        104 => (String::from(" Not A;Brand"), "99"),
+        _ => (
+            format!("Not{}A{}Brand", ESCAPED_CHARS[seed % 11], ESCAPED_CHARS[(seed + 1) % 11]),
+            GREASE_VERSIONS[seed % 3],
+        ),
+    };
+
+    let order = ORDERS[seed % 6];
+    let mut brands = [String::new(), String::new(), String::new()];
+    brands[order[0]] = format!("\"{}\";v=\"{}\"", grease, grease_version);
+    brands[order[1]] = format!("\"Chromium\";v=\"{}\"", version);
+    brands[order[2]] = format!("\"{}\";v=\"{}\"", brand, brand_version);
+    brands.join(", ")
+}
+
+fn chromium_headers(context: RequestContext, brands: String, user_agent: String, chromium: u16) -> Vec<(&'static str, String)> {
+    let user_agent = format!("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) {}", user_agent);
+    match context {
+        RequestContext::Navigation => vec![
+            ("sec-ch-ua", brands),
+            ("sec-ch-ua-mobile", String::from("?0")),
+            ("sec-ch-ua-platform", String::from("\"Windows\"")),
+            ("upgrade-insecure-requests", String::from("1")),
+            ("user-agent", user_agent),
+            ("accept", format!(
+                "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q={}",
+                if chromium >= 108 { "0.7" } else { "0.9" },
+            )),
+            ("sec-fetch-site", String::from("none")),
+            ("sec-fetch-mode", String::from("navigate")),
+            ("sec-fetch-user", String::from("?1")),
+            ("sec-fetch-dest", String::from("document")),
+            ("accept-encoding", String::from(ACCEPT_ENCODING)),
+            ("accept-language", String::from("en-US,en;q=0.9")),
+        ],
+        RequestContext::Xhr | RequestContext::Fetch => vec![
+            ("sec-ch-ua", brands),
+            ("sec-ch-ua-mobile", String::from("?0")),
+            ("user-agent", user_agent),
+            ("sec-ch-ua-platform", String::from("\"Windows\"")),
+            ("accept", String::from(ACCEPT_ANY)),
+            ("sec-fetch-site", String::from("same-origin")),
+            ("sec-fetch-mode", String::from("cors")),
+            ("sec-fetch-dest", String::from("empty")),
+            ("accept-encoding", String::from(ACCEPT_ENCODING)),
+            ("accept-language", String::from("en-US,en;q=0.9")),
+        ],
+    }
+}
+
+fn firefox_headers(context: RequestContext, version: u16) -> Vec<(&'static str, String)> {
+    let user_agent = format!("Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:{}.0) Gecko/20100101 Firefox/{}.0", version, version);
+    match context {
+        RequestContext::Navigation => vec![
+            ("user-agent", user_agent),
+            ("accept", String::from("text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8")),
+            ("accept-language", String::from("en-US,en;q=0.5")),
+            ("accept-encoding", String::from(ACCEPT_ENCODING)),
+            ("upgrade-insecure-requests", String::from("1")),
+            ("sec-fetch-dest", String::from("document")),
+            ("sec-fetch-mode", String::from("navigate")),
+            ("sec-fetch-site", String::from("none")),
+            ("sec-fetch-user", String::from("?1")),
+            ("te", String::from("trailers")),
+        ],
+        RequestContext::Xhr | RequestContext::Fetch => vec![
+            ("user-agent", user_agent),
+            ("accept", String::from(ACCEPT_ANY)),
+            ("accept-language", String::from("en-US,en;q=0.5")),
+            ("accept-encoding", String::from(ACCEPT_ENCODING)),
+            ("sec-fetch-dest", String::from("empty")),
+            ("sec-fetch-mode", String::from("cors")),
+            ("sec-fetch-site", String::from("same-origin")),
+            ("te", String::from("trailers")),
+        ],
+    }
+}
+
+/// Safari before 16.4 sends no `sec-fetch-*` headers.
+fn safari_headers(context: RequestContext, user_agent: String) -> Vec<(&'static str, String)> {
+    let accept = match context {
+        RequestContext::Navigation => "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+        RequestContext::Xhr | RequestContext::Fetch => ACCEPT_ANY,
+    };
+    vec![
+        ("accept", String::from(accept)),
+        ("user-agent", user_agent),
+        ("accept-language", String::from("en-US,en;q=0.9")),
+        ("accept-encoding", String::from(ACCEPT_ENCODING)),
+    ]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(headers: &[(&'static str, String)]) -> Vec<&'static str> {
+        headers.iter().map(|(name, _)| *name).collect()
+    }
+
+    fn value<'a>(headers: &'a [(&'static str, String)], name: &str) -> &'a str {
+        headers.iter().find(|(key, _)| *key == name).map(|(_, value)| value.as_str()).unwrap()
+    }
+
+    #[test]
+    fn test_chrome_profile() {
+        let headers = ClientIdentifier::Chrome120.default_headers(RequestContext::Navigation).unwrap();
+        assert_eq!(names(&headers), [
+            "sec-ch-ua", "sec-ch-ua-mobile", "sec-ch-ua-platform", "upgrade-insecure-requests", "user-agent",
+            "accept", "sec-fetch-site", "sec-fetch-mode", "sec-fetch-user", "sec-fetch-dest",
+            "accept-encoding", "accept-language",
+        ]);
+        assert_eq!(value(&headers, "sec-ch-ua"), r#""Not_A Brand";v="8", "Chromium";v="120", "Google Chrome";v="120""#);
+        assert!(value(&headers, "user-agent").contains("Chrome/120.0.0.0"));
+
+        let headers = ClientIdentifier::Chrome120.default_headers(RequestContext::Fetch).unwrap();
+        assert_eq!(value(&headers, "sec-fetch-mode"), "cors");
+        assert_eq!(value(&headers, "accept"), "*/*");
+        assert!(!names(&headers).contains(&"sec-fetch-user"));
+    }
+
+    #[test]
+    fn test_opera_uses_its_chromium_brands() {
+        let headers = ClientIdentifier::Opera89.default_headers(RequestContext::Navigation).unwrap();
+        assert_eq!(value(&headers, "sec-ch-ua"), r#"".Not/A)Brand";v="99", "Opera";v="89", "Chromium";v="103""#);
+        assert!(value(&headers, "user-agent").ends_with("Chrome/103.0.0.0 Safari/537.36 OPR/89.0.0.0"));
+    }
+
+    #[test]
+    fn test_firefox_and_safari_have_no_client_hints() {
+        for identifier in [ClientIdentifier::Firefox117, ClientIdentifier::Safari160, ClientIdentifier::SafariIOS155] {
+            let headers = identifier.default_headers(RequestContext::Xhr).unwrap();
+            assert!(names(&headers).iter().all(|name| !name.starts_with("sec-ch-ua")));
+        }
+
+        let headers = ClientIdentifier::SafariIOS155.default_headers(RequestContext::Navigation).unwrap();
+        assert!(value(&headers, "user-agent").contains("iPhone OS 15_5 like Mac OS X"));
+        assert!(ClientIdentifier::NikeIOSMobile.default_headers(RequestContext::Navigation).is_none());
+    }
+}
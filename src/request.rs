@@ -1,13 +1,26 @@
 use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use lazy_static::lazy_static;
 use reqwest::header::HeaderMap;
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use crate::cffi::{SHARED_METHODS};
-use crate::client::{CustomClient};
+use crate::backend::TlsBackend;
+use crate::cancel::CancellationToken;
+use crate::capability::Capability;
+use crate::client::{CustomClient, Session};
+use crate::cookie::Cookie;
 use crate::error::TlsClientError;
+use crate::loader::shared_call;
+use crate::pool::WorkerPool;
+use crate::profile::RequestContext;
 use crate::response::RequestResponse;
 use crate::types::ClientIdentifier;
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RequestPayload {
     catch_panics:                       Option<bool>,
@@ -44,20 +57,134 @@ pub struct RequestPayload {
     without_cookie_jar:                 Option<bool>,
     #[serde(rename = "withRandomTLSExtensionOrder")]
     with_random_tls_extension_order:    Option<bool>,
+
+    // Transport used by `send`, falls back to the shared library when unset
+    #[serde(skip)]
+    pub(crate) backend:                 Option<Arc<dyn TlsBackend>>,
+    // Enforced on the Rust side, see `deadline` and `cancel_token`
+    #[serde(skip)]
+    deadline:                           Option<Duration>,
+    #[serde(skip)]
+    cancel_token:                       Option<CancellationToken>,
+    // Set by `header_order`, stops headers added afterwards from being appended to the order
+    #[serde(skip)]
+    explicit_header_order:              bool,
+    // Added to the session before the request is sent, see `cookies`
+    #[serde(skip)]
+    session_cookies:                    Vec<Cookie>,
+    // Browser headers in use, `None` when the client sends none (see `context`)
+    #[serde(skip)]
+    context:                            Option<RequestContext>,
+    // Profile headers not yet replaced by the caller
+    #[serde(skip)]
+    profile_headers:                    Vec<String>,
+    // First problem found while building the request, returned by `send`
+    #[serde(skip)]
+    error:                              Option<TlsClientError>,
+}
+
+/// Whether `value` is a `token` as defined by RFC 7230, as required for methods and header names.
+pub(crate) fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Check `name` is a token and `value` only contains visible ASCII, spaces and tabs.
+fn validate_header(name: &str, value: &str) -> Result<(), TlsClientError> {
+    if !is_token(name) {
+        return Err(TlsClientError::InvalidHeader(format!("`{}` is not a valid header name", name)));
+    }
+    if !value.bytes().all(|b| b == b'\t' || (b' '..=b'~').contains(&b)) {
+        return Err(TlsClientError::InvalidHeader(format!("value of `{}` is not visible ASCII", name)));
+    }
+    Ok(())
+}
+
+/// Number of requests with a deadline or cancellation token running at once, further ones wait
+/// for a free thread.
+const GUARDED_PARALLELISM: usize = 32;
+
+lazy_static! {
+    static ref GUARDED_CALLS: GuardedCalls = GuardedCalls::new(GUARDED_PARALLELISM);
 }
 
 
 impl RequestPayload {
-    fn add_header(&mut self, key: &str, value: &str) {
+    /// Record a problem with the request for `send` to return, keeping the builder chainable.
+    pub(crate) fn fail(&mut self, error: TlsClientError) {
+        self.error.get_or_insert(error);
+    }
+
+    fn is_head(&self) -> bool {
+        self.request_method.as_deref() == Some("HEAD")
+    }
+
+    fn has_header(&self, name: &str) -> bool {
+        self.headers.as_ref().is_some_and(|headers| headers.keys().any(|key| key.eq_ignore_ascii_case(name)))
+    }
+
+    /// Set a header, replacing any value it had while keeping its place in the order.
+    fn set_header(&mut self, name: &str, value: &str) {
         if let Some(headers) = &mut self.headers {
-            headers.insert(key.to_string(), value.to_string());
-        } else {
-            let mut h = HashMap::new();
-            h.insert(key.to_string(), value.to_string());
-            self.headers = Some(h);
+            headers.retain(|key, _| !key.eq_ignore_ascii_case(name));
         }
+        self.header(name, value);
     }
-    
+
+    /// Swap the profile headers for the ones of `context`, keeping the headers set by the caller.
+    ///
+    /// They are sent as ordinary headers rather than `defaultHeaders`, which the library only reads
+    /// when it creates a session, so each request can use its own context.
+    pub(crate) fn apply_profile(&mut self, context: RequestContext) {
+        self.context = Some(context);
+        let profile = match self.tls_client_identifier.as_ref().and_then(|identifier| identifier.default_headers(context)) {
+            Some(profile) => profile,
+            None => return,
+        };
+        let previous = std::mem::take(&mut self.profile_headers);
+
+        let headers = self.headers.get_or_insert_with(HashMap::new);
+        headers.retain(|name, _| !previous.iter().any(|p| p.eq_ignore_ascii_case(name)));
+        if !self.explicit_header_order {
+            // Browser headers go in the browser's order, the caller's own ones after them
+            let custom: Vec<String> = self.header_order.take().unwrap_or_default().into_iter()
+                .filter(|name| !previous.contains(name) && !profile.iter().any(|(p, _)| p == name))
+                .collect();
+            self.header_order = Some(profile.iter().map(|(name, _)| name.to_string()).chain(custom).collect());
+        }
+        for (name, value) in profile {
+            if !headers.keys().any(|key| key.eq_ignore_ascii_case(name)) {
+                headers.insert(name.to_string(), value);
+                self.profile_headers.push(name.to_string());
+            }
+        }
+    }
+
+    /// Parse and normalise `url`, failing `send` with `TlsClientError::InvalidUrl` if it is
+    /// malformed or not http(s). An invalid url is kept as given, for error messages.
+    pub(crate) fn set_url(&mut self, url: &str) {
+        match Url::parse(url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {
+                self.request_url = Some(parsed.to_string());
+            }
+            Ok(parsed) => {
+                self.fail(TlsClientError::InvalidUrl(format!("`{}` has unsupported scheme `{}`", url, parsed.scheme())));
+                self.request_url = Some(url.to_string());
+            }
+            Err(e) => {
+                self.fail(TlsClientError::InvalidUrl(format!("`{}`: {}", url, e)));
+                self.request_url = Some(url.to_string());
+            }
+        }
+    }
+
+    pub(crate) fn session_id(&self) -> Option<&str> {
+        self.session_id.as_deref()
+    }
+
+    pub(crate) fn url(&self) -> Option<&str> {
+        self.request_url.as_deref()
+    }
+
     pub fn byte_response(&mut self) -> &mut Self {
         self.is_byte_response = Some(true);
         self
@@ -68,12 +195,116 @@ impl RequestPayload {
         self
     }
     
+    /// Replace the headers with `header_map`, sent in its iteration order (unless `header_order`
+    /// was set) after the browser headers it does not override. A value which is not visible
+    /// ASCII fails `send` with `TlsClientError::InvalidHeader`.
     pub fn headers(&mut self, header_map: HeaderMap) -> &mut Self {
-        let mut h = HashMap::new();
-        for (key, value) in header_map.iter() {
-            h.insert(key.as_str().to_string(), value.to_str().unwrap().to_string());
+        self.headers = None;
+        self.profile_headers.clear();
+        if !self.explicit_header_order {
+            self.header_order = None;
+        }
+        if let Some(context) = self.context {
+            self.apply_profile(context);
+        }
+
+        for (name, value) in header_map.iter() {
+            match value.to_str() {
+                Ok(value) => {
+                    self.header(name.as_str(), value);
+                }
+                Err(_) => self.fail(TlsClientError::InvalidHeader(format!("value of `{}` is not visible ASCII", name))),
+            }
+        }
+        self
+    }
+
+    /// Add a header after the ones already set. A browser header of the same name is replaced
+    /// in place.
+    ///
+    /// The library takes a single value per name, so a repeated header is combined with its
+    /// earlier values into one comma separated line (`; ` separated for `Cookie`), as RFC 7230
+    /// allows. An invalid name or a value which is not visible ASCII fails `send` with
+    /// `TlsClientError::InvalidHeader`.
+    pub fn header<K: AsRef<str>, V: AsRef<str>>(&mut self, name: K, value: V) -> &mut Self {
+        let (name, value) = (name.as_ref(), value.as_ref());
+        if let Err(e) = validate_header(name, value) {
+            self.fail(e);
+            return self;
+        }
+
+        let headers = self.headers.get_or_insert_with(HashMap::new);
+        if let Some(i) = self.profile_headers.iter().position(|p| p.eq_ignore_ascii_case(name)) {
+            headers.retain(|key, _| !key.eq_ignore_ascii_case(name));
+            self.profile_headers.remove(i);
+        }
+        match headers.iter_mut().find(|(key, _)| key.eq_ignore_ascii_case(name)) {
+            Some((_, values)) => {
+                values.push_str(if name.eq_ignore_ascii_case("cookie") { "; " } else { ", " });
+                values.push_str(value);
+            }
+            None => {
+                headers.insert(name.to_string(), value.to_string());
+                let order = self.header_order.get_or_insert_with(Vec::new);
+                let name = name.to_ascii_lowercase();
+                if !self.explicit_header_order && !order.contains(&name) {
+                    order.push(name);
+                }
+            }
+        }
+        self
+    }
+
+    /// Send headers in `order` instead of the order they were added in. Names are matched
+    /// case-insensitively, and headers missing from `order` are sent after the listed ones.
+    pub fn header_order<I, S>(&mut self, order: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.header_order = Some(order.into_iter().map(|name| name.as_ref().to_ascii_lowercase()).collect());
+        self.explicit_header_order = true;
+        self
+    }
+
+    /// Append `query` to the url's query string, encoded the way `serde_urlencoded` encodes a
+    /// struct, map or sequence of pairs. Existing query parameters are kept. A value which cannot
+    /// be encoded (e.g. a nested struct) fails `send` with `TlsClientError::InvalidUrl`.
+    pub fn query<T: Serialize + ?Sized>(&mut self, query: &T) -> &mut Self {
+        let encoded = match serde_urlencoded::to_string(query) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                self.fail(TlsClientError::InvalidUrl(format!("query could not be encoded: {}", e)));
+                return self;
+            }
+        };
+
+        // An invalid url has already failed the request
+        if let Some(Ok(mut url)) = self.request_url.as_deref().map(Url::parse) {
+            if !encoded.is_empty() {
+                let query = match url.query() {
+                    Some(existing) if !existing.is_empty() => format!("{}&{}", existing, encoded),
+                    _ => encoded,
+                };
+                url.set_query(Some(&query));
+                self.request_url = Some(url.to_string());
+            }
+        }
+        self
+    }
+
+    /// Append a single `name=value` parameter to the url's query string.
+    pub fn query_pair<K: AsRef<str>, V: AsRef<str>>(&mut self, name: K, value: V) -> &mut Self {
+        self.query(&[(name.as_ref(), value.as_ref())])
+    }
+
+    /// Send the browser headers of `context` instead of the navigation ones requests start with.
+    /// Does nothing if the client was built with `set_default_headers(false)` or has no browser
+    /// profile (see `ClientIdentifier::default_headers`).
+    pub fn context(&mut self, context: RequestContext) -> &mut Self {
+        if self.context.is_some() {
+            self.apply_profile(context);
         }
-        self.headers = Some(h);
         self
     }
 
@@ -83,17 +314,194 @@ impl RequestPayload {
     }
 
     pub fn json(&mut self, json: serde_json::Value) -> &mut Self {
-        self.add_header("Content-Type", "application/json");
+        self.set_header("Content-Type", "application/json");
         self.request_body = Some(serde_json::to_string(&json).unwrap());
         self
     }
 
+    /// Send `form` as an `application/x-www-form-urlencoded` body, accepting the same values as
+    /// `query`. A Content-Type header set before is kept. Values `query` rejects fail `send` with
+    /// `TlsClientError::InvalidRequest` here.
+    pub fn form<T: Serialize + ?Sized>(&mut self, form: &T) -> &mut Self {
+        match serde_urlencoded::to_string(form) {
+            Ok(body) => {
+                if !self.has_header("Content-Type") {
+                    self.header("Content-Type", "application/x-www-form-urlencoded");
+                }
+                self.request_body = Some(body);
+            }
+            Err(e) => self.fail(TlsClientError::InvalidRequest(format!("form could not be encoded: {}", e))),
+        }
+        self
+    }
+
+    /// Fail `send` with `TlsClientError::Timeout` if no response arrived within `deadline`.
+    ///
+    /// Unlike the timeout handed to the library this also covers calls which wedge inside it, e.g.
+    /// in a proxy CONNECT or a DNS stall.
+    pub fn deadline(&mut self, deadline: Duration) -> &mut Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Add `cookies` to the session's jar for this request's url before it is sent, so they are
+    /// kept for later requests like cookies set by a response. Needs a library exporting
+    /// `addCookiesToSession`, `send` fails with `TlsClientError::Unsupported` otherwise.
+    pub fn cookies(&mut self, cookies: &[Cookie]) -> &mut Self {
+        self.session_cookies.extend_from_slice(cookies);
+        self
+    }
+
+    /// Fail `send` with `TlsClientError::Cancelled` as soon as `token` is cancelled.
+    pub fn cancel_token(&mut self, token: &CancellationToken) -> &mut Self {
+        self.cancel_token = Some(token.clone());
+        self
+    }
+
+    /// Send the request, loading the shared library first if no backend was set and `init` has not
+    /// been called. Load and download failures are returned as errors.
+    ///
+    /// Problems found while building the request, such as an invalid method, are returned here.
     pub fn send(&self) -> Result<RequestResponse, TlsClientError> {
-        Ok(SHARED_METHODS.request(self))
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        if self.is_head() && self.request_body.is_some() {
+            return Err(TlsClientError::InvalidRequest(String::from("a HEAD request cannot have a body")));
+        }
+
+        // The shared backend is counted as in use for the whole send, so a swap waits for it
+        let shared;
+        let backend = match &self.backend {
+            Some(backend) => backend.clone(),
+            None => {
+                shared = shared_call()?;
+                shared.backend()
+            },
+        };
+
+        if let Some(info) = backend.library_info() {
+            for capability in self.required_capabilities() {
+                info.require(capability)?;
+            }
+        }
+
+        if let (false, Some(session_id), Some(url)) = (self.session_cookies.is_empty(), &self.session_id, &self.request_url) {
+            Session::new(session_id.clone(), Some(backend.clone())).add_typed_cookies(url, &self.session_cookies)?;
+        }
+
+        let mut response = match (self.deadline, &self.cancel_token) {
+            (None, None) => backend.request(self)?,
+            (deadline, token) => GUARDED_CALLS.send(self, backend, deadline, token.clone().unwrap_or_default())?,
+        };
+        // The library reports an empty body for HEAD, there is none
+        if self.is_head() {
+            response.body = None;
+        }
+        Ok(response)
+    }
+
+    /// Optional library exports needed to honour the options set on this request. The required
+    /// ones are checked when the library is loaded.
+    fn required_capabilities(&self) -> Vec<Capability> {
+        let mut capabilities = vec![];
+        if !self.session_cookies.is_empty() {
+            capabilities.push(Capability::AddCookiesToSession);
+        }
+        capabilities
     }
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+/// Worker threads running requests with a deadline or cancellation token, so the caller can give
+/// up on them.
+///
+/// A call abandoned while still queued is dropped without reaching the library. One abandoned
+/// once running still runs to completion in the background, and its response is freed like any
+/// other. It keeps its thread until then, so at most as many abandoned calls as there are threads
+/// may be outstanding, beyond that requests fail with `TlsClientError::TooManyAbandoned` instead
+/// of queueing behind calls which may never return.
+struct GuardedCalls {
+    pool:       WorkerPool,
+    abandoned:  Arc<AtomicUsize>,
+}
+
+impl GuardedCalls {
+    fn new(parallelism: usize) -> Self {
+        Self { pool: WorkerPool::new(parallelism), abandoned: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    fn send(
+        &self,
+        payload: &RequestPayload,
+        backend: Arc<dyn TlsBackend>,
+        deadline: Option<Duration>,
+        token: CancellationToken,
+    ) -> Result<RequestResponse, TlsClientError> {
+        if token.is_cancelled() {
+            return Err(TlsClientError::Cancelled);
+        }
+        let abandoned = self.abandoned.load(Ordering::SeqCst);
+        if abandoned >= self.pool.parallelism() {
+            return Err(TlsClientError::TooManyAbandoned(abandoned));
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let cancelled = sender.clone();
+        let _listener = token.on_cancel(move || {
+            let _ = cancelled.send(Err(TlsClientError::Cancelled));
+        });
+
+        // Set by whichever of the call and its caller is done first
+        let done = Arc::new(AtomicBool::new(false));
+        let call = CallDone { done: done.clone(), abandoned: self.abandoned.clone() };
+        let payload = payload.clone();
+        let job_token = token.clone();
+        self.pool.spawn(move || {
+            // The caller gave up while this was queued
+            if call.done.load(Ordering::SeqCst) || job_token.is_cancelled() {
+                return;
+            }
+            let _call = call;
+            let res = std::panic::catch_unwind(AssertUnwindSafe(|| backend.request(&payload)))
+                .unwrap_or_else(|_| Err(TlsClientError::GeneralError(String::from("Request thread panicked"))));
+            let _ = sender.send(res);
+        });
+
+        let res = match deadline {
+            Some(deadline) => receiver.recv_timeout(deadline),
+            None => receiver.recv().map_err(RecvTimeoutError::from),
+        };
+        let res = res.unwrap_or_else(|e| Err(match e {
+            RecvTimeoutError::Timeout => TlsClientError::Timeout(deadline.unwrap_or_default()),
+            RecvTimeoutError::Disconnected => TlsClientError::GeneralError(String::from("Request thread exited")),
+        }));
+
+        if matches!(res, Err(TlsClientError::Timeout(_) | TlsClientError::Cancelled)) {
+            self.abandoned.fetch_add(1, Ordering::SeqCst);
+            // The call finished meanwhile, it holds no thread
+            if done.swap(true, Ordering::SeqCst) {
+                self.abandoned.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        res
+    }
+}
+
+/// Marks a guarded call as finished once dropped, releasing its slot if the caller gave up on it.
+struct CallDone {
+    done:       Arc<AtomicBool>,
+    abandoned:  Arc<AtomicUsize>,
+}
+
+impl Drop for CallDone {
+    fn drop(&mut self) {
+        if self.done.swap(true, Ordering::SeqCst) {
+            self.abandoned.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct TransportOptions {
     disable_keep_alives:        bool,
     disable_compression:        bool,
@@ -107,4 +515,284 @@ pub struct TransportOptions {
 }
 
 
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use reqwest::header::HeaderValue;
+    use serde_json::{json, Value};
+    use crate::backend::tests::{fake_client, FakeBackend};
+    use crate::client::TlsClient;
+    use super::*;
+
+    fn slow_client(delay: Duration) -> (Arc<FakeBackend>, TlsClient) {
+        fake_client(FakeBackend {
+            delay: Some(delay),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_deadline_times_out_and_call_completes() {
+        let (backend, client) = slow_client(Duration::from_millis(300));
+
+        let started = Instant::now();
+        let res = client.get("https://example.com").deadline(Duration::from_millis(50)).send();
+        assert!(matches!(res, Err(TlsClientError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_millis(250));
+
+        // The late call is not abandoned mid-way
+        std::thread::sleep(Duration::from_millis(500));
+        assert_eq!(backend.payloads.lock().unwrap().len(), 1);
+    }
+
+    fn sent_headers(payload: &mut RequestPayload) -> (Value, Value) {
+        let backend = Arc::new(FakeBackend::default());
+        payload.backend = Some(backend.clone());
+        payload.send().unwrap();
+        let sent = backend.payloads.lock().unwrap()[0].clone();
+        (sent["headers"].clone(), sent["headerOrder"].clone())
+    }
+
+    #[test]
+    fn test_ordered_headers() {
+        let client = TlsClient::default().set_default_headers(false);
+        let mut payload = client.get("https://example.com");
+        payload.header("User-Agent", "test")
+            .header("Accept", "text/html")
+            .header("accept", "*/*")
+            .header("Cookie", "a=1")
+            .header("Cookie", "b=2")
+            .json(json!({}))
+            .json(json!({ "replaced": true }));
+
+        let (headers, order) = sent_headers(&mut payload);
+        assert_eq!(headers, json!({
+            "User-Agent": "test",
+            "Accept": "text/html, */*",
+            "Cookie": "a=1; b=2",
+            "Content-Type": "application/json",
+        }));
+        assert_eq!(order, json!(["user-agent", "accept", "cookie", "content-type"]));
+
+        let mut payload = client.get("https://example.com");
+        payload.header_order(["Accept", "User-Agent"])
+            .header("User-Agent", "test")
+            .header("Accept", "*/*");
+        assert_eq!(sent_headers(&mut payload).1, json!(["accept", "user-agent"]));
+    }
+
+    #[test]
+    fn test_header_map_keeps_order() {
+        let mut map = HeaderMap::new();
+        map.insert("x-first", HeaderValue::from_static("1"));
+        map.insert("accept", HeaderValue::from_static("text/html"));
+        map.append("accept", HeaderValue::from_static("*/*"));
+
+        let mut payload = TlsClient::default().set_default_headers(false).get("https://example.com");
+        payload.header("dropped", "1").headers(map);
+        let (headers, order) = sent_headers(&mut payload);
+        assert_eq!(headers, json!({ "x-first": "1", "accept": "text/html, */*" }));
+        assert_eq!(order, json!(["x-first", "accept"]));
+    }
+
+    #[test]
+    fn test_browser_headers_are_overridable() {
+        let client = TlsClient::new(ClientIdentifier::Firefox117, false);
+        let mut payload = client.get("https://example.com");
+        payload.header("X-Custom", "1").header("User-Agent", "test");
+
+        let (headers, order) = sent_headers(&mut payload);
+        assert_eq!(headers["User-Agent"], "test");
+        assert!(headers.get("user-agent").is_none());
+        assert_eq!(headers["sec-fetch-mode"], "navigate");
+        assert_eq!(order, json!([
+            "user-agent", "accept", "accept-language", "accept-encoding", "upgrade-insecure-requests",
+            "sec-fetch-dest", "sec-fetch-mode", "sec-fetch-site", "sec-fetch-user", "te", "x-custom",
+        ]));
+
+        // Switching context swaps the browser headers and keeps the caller's
+        payload.context(RequestContext::Fetch);
+        let (headers, order) = sent_headers(&mut payload);
+        assert_eq!(headers["User-Agent"], "test");
+        assert_eq!(headers["X-Custom"], "1");
+        assert_eq!(headers["accept"], "*/*");
+        assert!(headers.get("upgrade-insecure-requests").is_none());
+        assert_eq!(order.as_array().unwrap().last().unwrap(), "x-custom");
+
+        let mut map = HeaderMap::new();
+        map.insert("accept", HeaderValue::from_static("text/html"));
+        payload.headers(map);
+        let (headers, _) = sent_headers(&mut payload);
+        assert_eq!(headers["accept"], "text/html");
+        assert_eq!(headers["sec-fetch-mode"], "cors");
+        assert!(headers.get("X-Custom").is_none());
+    }
+
+    #[test]
+    fn test_browser_headers_can_be_disabled() {
+        let mut payload = TlsClient::default().set_default_headers(false).get("https://example.com");
+        payload.context(RequestContext::Xhr);
+        assert_eq!(sent_headers(&mut payload), (Value::Null, Value::Null));
 
+        let mut payload = TlsClient::new(ClientIdentifier::NikeIOSMobile, false).get("https://example.com");
+        assert_eq!(sent_headers(&mut payload), (Value::Null, Value::Null));
+    }
+
+    #[test]
+    fn test_invalid_headers_fail_send() {
+        let (_, client) = fake_client(FakeBackend::default());
+
+        let mut map = HeaderMap::new();
+        map.insert("x-name", HeaderValue::from_bytes("Zoë".as_bytes()).unwrap());
+        assert!(matches!(client.get("https://example.com").headers(map).send(), Err(TlsClientError::InvalidHeader(_))));
+        assert!(matches!(client.get("https://example.com").header("x-name", "Zoë").send(), Err(TlsClientError::InvalidHeader(_))));
+        assert!(matches!(client.get("https://example.com").header("x-a\r\nb", "1").send(), Err(TlsClientError::InvalidHeader(_))));
+        assert!(matches!(client.get("https://example.com").header("x-name", "a\r\nb").send(), Err(TlsClientError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_query_is_encoded() {
+        #[derive(Serialize)]
+        struct Search<'a> {
+            q: &'a str,
+            page: u32,
+            tag: Option<&'a str>,
+        }
+
+        let client = TlsClient::default();
+        let mut payload = client.get("https://example.com/search?lang=en#results");
+        payload.query(&Search { q: "a b&c=d", page: 2, tag: None })
+            .query_pair("emoji", "ö/€");
+        assert_eq!(payload.url(), Some("https://example.com/search?lang=en&q=a+b%26c%3Dd&page=2&emoji=%C3%B6%2F%E2%82%AC#results"));
+
+        let mut payload = client.get("HTTPS://Example.com");
+        payload.query(&[("a", "1")]).query(&Vec::<(String, String)>::new());
+        assert_eq!(payload.url(), Some("https://example.com/?a=1"));
+    }
+
+    #[test]
+    fn test_invalid_url_fails_send() {
+        let (backend, client) = fake_client(FakeBackend::default());
+
+        for url in ["example.com", "https://exa mple.com", "ftp://example.com", ""] {
+            assert!(matches!(client.get(url).send(), Err(TlsClientError::InvalidUrl(_))), "{}", url);
+        }
+        let res = client.get("https://example.com").query(&json!({ "nested": { "a": 1 } })).send();
+        assert!(matches!(res, Err(TlsClientError::InvalidUrl(_))));
+        assert!(backend.payloads.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_form_body() {
+        #[derive(Serialize)]
+        struct Login<'a> {
+            user: &'a str,
+            password: &'a str,
+            remember: bool,
+        }
+
+        let client = TlsClient::default().set_default_headers(false);
+        let mut payload = client.post("https://example.com/login");
+        payload.form(&Login { user: "zoë", password: "a&b=c d", remember: true });
+        let backend = Arc::new(FakeBackend::default());
+        payload.backend = Some(backend.clone());
+        payload.send().unwrap();
+        let sent = backend.payloads.lock().unwrap()[0].clone();
+        assert_eq!(sent["requestBody"], "user=zo%C3%AB&password=a%26b%3Dc+d&remember=true");
+        assert_eq!(sent["headers"], json!({ "Content-Type": "application/x-www-form-urlencoded" }));
+
+        let mut payload = client.post("https://example.com/login");
+        payload.header("content-type", "application/x-www-form-urlencoded; charset=UTF-8")
+            .form(&[("a", "1"), ("a", "2")]);
+        let (headers, _) = sent_headers(&mut payload);
+        assert_eq!(headers, json!({ "content-type": "application/x-www-form-urlencoded; charset=UTF-8" }));
+
+        let res = client.post("https://example.com").form(&json!({ "nested": [1, 2] })).send();
+        assert!(matches!(res, Err(TlsClientError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn test_is_token() {
+        assert!(is_token("GET"));
+        assert!(is_token("M-SEARCH"));
+        assert!(is_token("x~1"));
+        assert!(!is_token(""));
+        assert!(!is_token("GET /"));
+        assert!(!is_token("GÉT"));
+        assert!(!is_token("(GET)"));
+    }
+
+    #[test]
+    fn test_deadline_allows_fast_response() {
+        let (_, client) = slow_client(Duration::from_millis(10));
+        let res = client.get("https://example.com").deadline(Duration::from_secs(5)).send();
+        assert_eq!(res.unwrap().target, "https://example.com/");
+    }
+
+    #[test]
+    fn test_cancellation_token() {
+        let (_, client) = slow_client(Duration::from_secs(1));
+        let token = CancellationToken::new();
+
+        let canceller = {
+            let token = token.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                token.cancel();
+            })
+        };
+
+        let started = Instant::now();
+        let res = client.get("https://example.com").cancel_token(&token).send();
+        assert!(matches!(res, Err(TlsClientError::Cancelled)));
+        assert!(started.elapsed() < Duration::from_millis(500));
+        canceller.join().unwrap();
+
+        // An already cancelled token fails without sending
+        let res = client.get("https://example.com").cancel_token(&token).send();
+        assert!(matches!(res, Err(TlsClientError::Cancelled)));
+    }
+
+    #[test]
+    fn test_abandoned_calls_are_limited() {
+        let (backend, client) = slow_client(Duration::from_millis(200));
+        let calls = GuardedCalls::new(1);
+        let deadline = Some(Duration::from_millis(20));
+        let send = || calls.send(&client.get("https://example.com"), backend.clone(), deadline, CancellationToken::new());
+
+        assert!(matches!(send(), Err(TlsClientError::Timeout(_))));
+        // The abandoned call still holds the only thread
+        assert!(matches!(send(), Err(TlsClientError::TooManyAbandoned(1))));
+
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(calls.abandoned.load(Ordering::SeqCst), 0);
+        let res = calls.send(&client.get("https://example.com"), backend.clone(), None, CancellationToken::new());
+        assert!(res.is_ok());
+        assert_eq!(backend.payloads.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_cancelled_queued_call_is_not_sent() {
+        let (backend, client) = slow_client(Duration::from_millis(200));
+        let calls = GuardedCalls::new(1);
+        let token = CancellationToken::new();
+
+        std::thread::scope(|scope| {
+            let running = scope.spawn(|| calls.send(&client.get("https://example.com"), backend.clone(), None, CancellationToken::new()));
+            std::thread::sleep(Duration::from_millis(20));
+
+            // Queued behind the running call on the only thread
+            let queued = scope.spawn(|| calls.send(&client.get("https://example.com"), backend.clone(), None, token.clone()));
+            std::thread::sleep(Duration::from_millis(20));
+            token.cancel();
+
+            assert!(matches!(queued.join().unwrap(), Err(TlsClientError::Cancelled)));
+            assert!(running.join().unwrap().is_ok());
+        });
+
+        // Long enough for the queued call to have been sent had it not been dropped
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(calls.abandoned.load(Ordering::SeqCst), 0);
+        assert_eq!(backend.payloads.lock().unwrap().len(), 1);
+    }
+}
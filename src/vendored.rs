@@ -0,0 +1,18 @@
+use crate::cache::{BinaryCache, CacheEntry};
+use crate::error::TlsClientError;
+use crate::loader::InitOptions;
+
+
+// Resolved by `build.rs` for the target triple, see there for configuration
+static VENDORED_LIBRARY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/tls-client-vendored"));
+const VENDORED_VERSION: &str = env!("RUST_TLS_CLIENT_VENDORED_VERSION");
+
+
+/// Write the library embedded at build time into the binary cache, reusing a previous extraction
+/// when its contents still match. `None` if `build.rs` had no library to embed.
+pub(crate) fn extract(options: &InitOptions) -> Result<Option<CacheEntry>, TlsClientError> {
+    if VENDORED_LIBRARY.is_empty() {
+        return Ok(None);
+    }
+    BinaryCache::new(options)?.store(VENDORED_VERSION, VENDORED_LIBRARY).map(Some)
+}